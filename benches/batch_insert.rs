@@ -0,0 +1,100 @@
+// Compare the per-row and the batched write path for MultipleData records.
+//
+// Needs a MySQL server with the weatherstation tables, the connection is configured
+// with the usual environment variables (SBD_DB_HOST, SBD_DB_NAME, SBD_DB_USER, SBD_DB_PASSWORD, ...).
+// All writes are done in transactions that are rolled back, so nothing is left in the database.
+// The records are written a second time to check the unchanged count, see count_batch_upsert().
+//
+// SBD_DB_NAME=weatherstation_test cargo bench --bench batch_insert
+
+// External crates:
+extern crate chrono;
+extern crate sbd_station_db_import;
+
+// External modules:
+use chrono::{NaiveDate, Duration};
+
+// System modules:
+use std::env;
+use std::time::Instant;
+
+// Internal modules:
+use sbd_station_db_import::error::{Result};
+use sbd_station_db_import::data_parser::{Record};
+use sbd_station_db_import::database::{Table, ImportCount};
+use sbd_station_db_import::layout::{builtin_layout};
+use sbd_station_db_import::mysql_storage::{MySqlStorage, import_rows, import_batched};
+use sbd_station_db_import::db_config::{DbConfig};
+use sbd_station_db_import::password::{read_password};
+
+const STATION_NAME: &str = "__benchmark__";
+const NUM_OF_RECORDS: usize = 2000;
+
+fn records(num_of_columns: usize) -> Vec<Record> {
+    let start = NaiveDate::from_ymd_opt(1990, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+
+    (0..NUM_OF_RECORDS).map(|i| {
+        let value = (i % 100) as f64;
+
+        Record {
+            offset: 0,
+            date_time: start + Duration::hours(i as i64),
+            values: vec![value; num_of_columns],
+        }
+    }).collect()
+}
+
+fn run(db_config: &DbConfig, db_password: Option<&str>, batch_size: usize) -> Result<()> {
+    let layout = builtin_layout("multiple")?;
+    let columns = layout.records[0].columns();
+    let data = records(columns.len());
+    let mut storage = MySqlStorage::connect(db_config, db_password)?;
+    let mut db_tx = storage.start_transaction()?;
+
+    let start = Instant::now();
+
+    let import_count: ImportCount = if batch_size > 1 {
+        import_batched(&mut db_tx, Table::MultipleData, STATION_NAME, &columns, &data, batch_size)?
+    } else {
+        import_rows(&mut db_tx, Table::MultipleData, STATION_NAME, &columns, &data)?
+    };
+
+    let elapsed = start.elapsed();
+
+    let again: ImportCount = if batch_size > 1 {
+        import_batched(&mut db_tx, Table::MultipleData, STATION_NAME, &columns, &data, batch_size)?
+    } else {
+        import_rows(&mut db_tx, Table::MultipleData, STATION_NAME, &columns, &data)?
+    };
+
+    db_tx.rollback()?;
+
+    if again != (ImportCount { inserted: 0, updated: 0, unchanged: data.len() as u64 }) {
+        return Err(format!("Wrong count for records written again: {:?}", again).into());
+    }
+
+    println!("batch size: {:>5}, records: {}, inserted: {}, time: {:>10.3} ms, {:>8.1} records/s",
+        batch_size, data.len(), import_count.inserted, elapsed.as_secs_f64() * 1000.0,
+        data.len() as f64 / elapsed.as_secs_f64());
+
+    Ok(())
+}
+
+fn main() {
+    if env::var("SBD_DB_NAME").is_err() {
+        println!("SBD_DB_NAME not set, skipping benchmark");
+        return;
+    }
+
+    let mut db_config = DbConfig::default();
+    db_config.override_from_env().unwrap();
+    db_config.validate().unwrap();
+    let db_password = read_password(&db_config, None).unwrap();
+
+    for batch_size in &[1, 10, 100, 500, 1000] {
+        if let Err(e) = run(&db_config, db_password.as_deref(), *batch_size) {
+            println!("benchmark failed: {}", e);
+            return;
+        }
+    }
+}
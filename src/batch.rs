@@ -0,0 +1,277 @@
+// External modules:
+use glob::{glob};
+use chrono::{Utc};
+
+// System modules:
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Internal modules:
+use error::{Result, ResultExt};
+use data_parser::{parse_data};
+use database::{ImportCount, RawMessage, Storage, archive_skipped, import_raw_to_db};
+use dry_run::{dry_run, count_diffs};
+use station::{StationRegistry, parse_sbd_file_name};
+use email::{SbdMessage};
+
+/// What happened to one file of a batch import.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileResult {
+    /// The rows written and the parse problems that did not reject the payload, see parse_data()
+    Imported(ImportCount, Vec<String>),
+    /// The same payload (SHA-256) was imported before, nothing was written
+    Duplicate(String),
+    Skipped(String),
+    Failed(String),
+}
+
+/// The result of all files of a batch import.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BatchSummary {
+    pub import_count: ImportCount,
+    pub imported: Vec<PathBuf>,
+    /// One entry per parse problem of an imported file
+    pub problems: Vec<(PathBuf, String)>,
+    pub duplicates: Vec<PathBuf>,
+    pub skipped: Vec<(PathBuf, String)>,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl BatchSummary {
+    pub fn add(&mut self, file: PathBuf, result: FileResult) {
+        match result {
+            FileResult::Imported(import_count, problems) => {
+                self.import_count.inserted += import_count.inserted;
+                self.import_count.updated += import_count.updated;
+                self.import_count.unchanged += import_count.unchanged;
+                self.problems.extend(problems.into_iter().map(|problem| (file.clone(), problem)));
+                self.imported.push(file);
+            },
+            FileResult::Duplicate(_) => self.duplicates.push(file),
+            FileResult::Skipped(reason) => self.skipped.push((file, reason)),
+            FileResult::Failed(reason) => self.failed.push((file, reason)),
+        }
+    }
+
+    /// 0: no file failed, 2: some files failed, 1: nothing was imported because all files failed or were skipped,
+    /// e.g. a single file of a station missing in the config file
+    pub fn exit_code(&self) -> i32 {
+        let nothing_imported = self.imported.is_empty() && self.duplicates.is_empty();
+
+        if nothing_imported && !(self.failed.is_empty() && self.skipped.is_empty()) {
+            1
+        } else if self.failed.is_empty() {
+            0
+        } else {
+            2
+        }
+    }
+}
+
+fn is_glob_pattern(input: &str) -> bool {
+    input.contains('*') || input.contains('?') || input.contains('[')
+}
+
+fn is_sbd_file(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|extension| extension == "sbd")
+}
+
+/// Expand the inputs given on the command line: files are used as they are,
+/// directories and glob patterns are expanded to the .sbd files they contain.
+pub fn collect_files(inputs: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        let path = Path::new(input);
+
+        if path.is_dir() {
+            let entries = fs::read_dir(path).chain_err(|| format!("Could not read directory: '{}'", input))?;
+            for entry in entries {
+                let entry_path = entry?.path();
+                if is_sbd_file(&entry_path) {
+                    files.push(entry_path);
+                }
+            }
+        } else if !path.exists() && is_glob_pattern(input) {
+            let paths = glob(input).chain_err(|| format!("Invalid glob pattern: '{}'", input))?;
+            for entry_path in paths {
+                let entry_path = entry_path.chain_err(|| format!("Could not read files matching: '{}'", input))?;
+                if is_sbd_file(&entry_path) {
+                    files.push(entry_path);
+                }
+            }
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    files.sort();
+    files.dedup();
+
+    Ok(files)
+}
+
+/// Sort the files by IMEI and MOMSN, so the messages of each station are imported in the order they were sent.
+/// Files without an Iridium file name come last, sorted by path.
+pub fn sort_by_momsn(files: &mut [PathBuf]) {
+    files.sort_by_key(|file| {
+        match file.to_str().and_then(parse_sbd_file_name) {
+            Some(sbd_file_name) => (false, sbd_file_name.imei, sbd_file_name.momsn, file.clone()),
+            None => (true, String::new(), 0, file.clone()),
+        }
+    });
+}
+
+/// Import (or with dry_run only compare) one file in its own transaction.
+/// Files that do not belong to a known station are skipped, all other errors make the file fail.
+pub fn import_file(storage: &mut dyn Storage, station_registry: &StationRegistry, file: &Path,
+        station_override: Option<&str>, dry_run_only: bool) -> FileResult {
+    match fs::read(file) {
+        Ok(binary_data) => import_data(storage, station_registry, &file.to_string_lossy(), binary_data, None, station_override, dry_run_only),
+        Err(e) => FileResult::Failed(format!("Could not read file: {}", e))
+    }
+}
+
+/// Archive a skipped payload with its reason, so reprocess can import it later.
+/// Nothing is written with dry_run, a payload that can not be archived makes the file fail.
+fn skip(storage: &mut dyn Storage, file_name: &str, station_name: Option<&str>, binary_data: Vec<u8>,
+        sbd_message: Option<&SbdMessage>, reason: String, dry_run_only: bool) -> FileResult {
+    if !dry_run_only {
+        let raw_message = RawMessage::new(file_name, station_name, binary_data, sbd_message);
+
+        if let Err(e) = archive_skipped(storage, &raw_message, &reason) {
+            return FileResult::Failed(format!("{}, could not archive the payload: {}", reason, e))
+        }
+    }
+
+    FileResult::Skipped(reason)
+}
+
+/// Import the content of an SBD file, the file name is used to determine the station.
+/// The metadata of the message is stored with it if the file came from an Iridium e-mail.
+pub fn import_data(storage: &mut dyn Storage, station_registry: &StationRegistry, file_name: &str, binary_data: Vec<u8>,
+        sbd_message: Option<&SbdMessage>, station_override: Option<&str>, dry_run_only: bool) -> FileResult {
+    // Without --station a file of an unknown station is not an error, it is just not ours
+    let station = match station_registry.resolve(file_name, station_override) {
+        Ok(station) => station,
+        Err(e) => if station_override.is_none() {
+            return skip(storage, file_name, None, binary_data, sbd_message, e.to_string(), dry_run_only)
+        } else {
+            return FileResult::Failed(e.to_string())
+        }
+    };
+
+    if binary_data.is_empty() {
+        return skip(storage, file_name, Some(&station.name), binary_data, sbd_message, "Empty file".to_string(), dry_run_only)
+    }
+
+    let result = if dry_run_only {
+        parse_data(&binary_data, &station.layout, Utc::now().naive_utc()).and_then(|data| {
+            let diffs = dry_run(storage, station, &data)?;
+            for diff in &diffs {
+                print!("{}", diff);
+            }
+            Ok((count_diffs(&diffs), data.problems))
+        })
+    } else {
+        let raw_message = RawMessage::new(file_name, Some(&station.name), binary_data, sbd_message);
+
+        match storage.is_imported(&raw_message.sha256) {
+            Ok(true) => {
+                info!("Payload already imported, sha256: {}", raw_message.sha256);
+                return FileResult::Duplicate(raw_message.sha256)
+            },
+            Ok(false) => import_raw_to_db(storage, station, &raw_message, sbd_message),
+            Err(e) => Err(e)
+        }
+    };
+
+    match result {
+        Ok((import_count, problems)) => FileResult::Imported(import_count, problems),
+        Err(e) => FileResult::Failed(e.to_string())
+    }
+}
+
+/// Import all files in MOMSN order, one transaction per file.
+pub fn import_files(storage: &mut dyn Storage, station_registry: &StationRegistry, mut files: Vec<PathBuf>,
+        station_override: Option<&str>, dry_run_only: bool) -> BatchSummary {
+    let mut summary = BatchSummary::default();
+
+    sort_by_momsn(&mut files);
+
+    for file in files {
+        info!("Importing file: '{}'", file.display());
+
+        let result = import_file(storage, station_registry, &file, station_override, dry_run_only);
+
+        match result {
+            FileResult::Imported(_, ref problems) => for problem in problems {
+                warn!("Parse problem in file: '{}': {}", file.display(), problem);
+            },
+            FileResult::Duplicate(ref sha256) => info!("File already imported: '{}', sha256: {}", file.display(), sha256),
+            FileResult::Skipped(ref reason) => warn!("Skipped file: '{}': {}", file.display(), reason),
+            FileResult::Failed(ref reason) => error!("Import failed for file: '{}': {}", file.display(), reason),
+        }
+
+        summary.add(file, result);
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{PathBuf};
+
+    use database::{ImportCount};
+
+    use super::{BatchSummary, FileResult, sort_by_momsn};
+
+    #[test]
+    fn test_sort_by_momsn() {
+        let mut files: Vec<PathBuf> = [
+            "inbox/300025060007390_10.sbd",
+            "notes.sbd",
+            "300025060000500_3.sbd",
+            "inbox/300025060007390_9.sbd",
+            "other/300025060007390_11.sbd",
+        ].iter().map(PathBuf::from).collect();
+
+        sort_by_momsn(&mut files);
+
+        let expected: Vec<PathBuf> = [
+            "300025060000500_3.sbd",
+            "inbox/300025060007390_9.sbd",
+            "inbox/300025060007390_10.sbd",
+            "other/300025060007390_11.sbd",
+            "notes.sbd",
+        ].iter().map(PathBuf::from).collect();
+
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let mut summary = BatchSummary::default();
+        assert_eq!(summary.exit_code(), 0);
+
+        summary.add(PathBuf::from("a.sbd"), FileResult::Failed("parse error".to_string()));
+        assert_eq!(summary.exit_code(), 1);
+
+        summary.add(PathBuf::from("b.sbd"), FileResult::Imported(ImportCount { inserted: 1, updated: 0, unchanged: 0 },
+            vec!["partial record at byte 28".to_string()]));
+        assert_eq!(summary.exit_code(), 2);
+        assert_eq!(summary.problems, vec![(PathBuf::from("b.sbd"), "partial record at byte 28".to_string())]);
+        summary.add(PathBuf::from("b.sbd"), FileResult::Duplicate("4c5a".to_string()));
+        assert_eq!(summary.duplicates.len(), 1);
+        assert_eq!(summary.import_count.inserted, 1);
+
+        // A file of an unknown station is not an error in a directory, but nothing at all must not look like success
+        let mut summary = BatchSummary::default();
+        summary.add(PathBuf::from("300025060000500_3.sbd"), FileResult::Skipped("Unknown IMEI: 300025060000500".to_string()));
+        assert_eq!(summary.exit_code(), 1);
+
+        summary.add(PathBuf::from("300025060007390_2.sbd"), FileResult::Imported(ImportCount { inserted: 1, updated: 0, unchanged: 0 }, Vec::new()));
+        assert_eq!(summary.exit_code(), 0);
+    }
+}
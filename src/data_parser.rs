@@ -1,47 +1,83 @@
 
 // External modules:
-use nom::{le_u32, be_u16, IResult};
-use chrono::{NaiveDateTime};
+use nom::{le_u32, IResult};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Duration};
 use combine::{RangeStream};
 use combine::parser::byte::num;
 
 // System modules:
-use std::f64::{INFINITY, NEG_INFINITY, NAN};
+use std::fmt;
 
 
 // Internal modules:
-use error::{Result};
-
-#[derive(Debug, PartialEq)]
-pub struct SimpleDataType {
+use error::{ErrorKind, Result};
+use layout::{RecordDefinition, RecordLayout, Strictness};
+
+/// One decoded record: the timestamp and the values in the order of the fields of the record definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Position of the first byte of the record in the payload
+    pub offset: usize,
     pub date_time: NaiveDateTime,
-    pub solar_battery_voltage: f64,
-    pub lithium_battery_voltage: f64,
-    pub wind_direction: f64,
+    pub values: Vec<f64>,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct MultipleDataType {
-    pub date_time: NaiveDateTime,
-    pub air_temperature: f64,
-    pub air_relative_humidity: f64,
-    pub solar_radiation: f64,
-    pub soil_water_content: f64,
-    pub soil_temperature: f64,
-    pub wind_speed: f64,
-    pub wind_max: f64,
-    pub wind_direction: f64,
-    pub precipitation: f64,
-    pub air_pressure: f64,
+/// The rule that chose the record definition for a payload, see choose_candidate().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Detection {
+    /// The layout has only one record definition
+    SingleDefinition,
+    /// The payload starts with the header of the record definition
+    Header,
+    /// Only this record definition matches the length of the payload exactly
+    Length,
+    /// Only this record definition gives plausible timestamps
+    Plausibility,
+    /// No rule decided, the first matching record definition of the layout was used
+    LayoutOrder,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rule = match *self {
+            Detection::SingleDefinition => "single record definition",
+            Detection::Header => "header",
+            Detection::Length => "exact length",
+            Detection::Plausibility => "plausible timestamps",
+            Detection::LayoutOrder => "layout order",
+        };
+
+        write!(f, "{}", rule)
+    }
+}
+
+/// The records of one payload and the record definition that was used to decode them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeatherStationData {
+    pub definition: RecordDefinition,
+    pub detection: Detection,
+    pub records: Vec<Record>,
+    /// The parse problems that did not reject the payload, with their byte offset, see parse_data()
+    pub problems: Vec<String>,
+}
+
+/// The bit fields of a Campbell FP2 value, see u16_to_f64().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fp2Parts {
+    pub negative: bool,
+    pub exponent: u16,
+    pub mantissa: u16,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum WeatherStationData {
-    SimpleData(SimpleDataType),
-    MultipleData(Vec<MultipleDataType>),
+pub fn split_fp2(data: u16) -> Fp2Parts {
+    Fp2Parts {
+        negative: data & 0b10000000_00000000 != 0,
+        exponent: (data & 0b01100000_00000000) >> 13,
+        mantissa: data & 0b00011111_11111111,
+    }
 }
 
-fn u16_to_f64(data: u16) -> f64 {
+pub fn u16_to_f64(data: u16) -> f64 {
     // base16 2 byte floats:
     // https://en.wikipedia.org/wiki/Half-precision_floating-point_format
     // https://github.com/sgothel/jogl/blob/master/src/jogl/classes/com/jogamp/opengl/math/Binary16.java
@@ -78,18 +114,18 @@ fn u16_to_f64(data: u16) -> f64 {
     const F2_NAN: u16 = 0b10011111_11111110; // 159, 254
 
     if data == F2_POS_INFINITY {
-        INFINITY
+        f64::INFINITY
     } else if data == F2_NEG_INFINITY {
-        NEG_INFINITY
+        f64::NEG_INFINITY
     } else if data == F2_NAN {
-        NAN
+        f64::NAN
     } else {
-        let sign = if data & 0b10000000_00000000 == 0 { 1.0 } else { - 1.0 };
+        let parts = split_fp2(data);
+        let sign = if parts.negative { - 1.0 } else { 1.0 };
 
-        let mantissa: f64 = ((data & 0b00011111_11111111) as f64) * sign;
-        let exponent: u16 = (data & 0b01100000_00000000) >> 13;
+        let mantissa: f64 = (parts.mantissa as f64) * sign;
 
-        match exponent {
+        match parts.exponent {
             1 => mantissa / 10.0,
             2 => mantissa / 100.0,
             3 => mantissa / 1000.0,
@@ -98,88 +134,274 @@ fn u16_to_f64(data: u16) -> f64 {
     }
 }
 
+/// The bit fields of a Campbell FP4 value, see fp4_to_f64().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fp4Parts {
+    pub negative: bool,
+    pub exponent: u8,
+    pub mantissa: u32,
+}
+
+pub fn split_fp4(data: u32) -> Fp4Parts {
+    Fp4Parts {
+        negative: data & 0x80000000 != 0,
+        exponent: ((data & 0x7f000000) >> 24) as u8,
+        mantissa: data & 0x00ffffff,
+    }
+}
+
+pub fn fp4_to_f64(data: u32) -> f64 {
+    // Campbells own 4 bytes floating point format:
+    // Bits: ABBBBBBB CCCCCCCC CCCCCCCC CCCCCCCC
+    //
+    // A: Sign, 0: +, 1: -
+    //
+    // B: Base 2 exponent, excess 64
+    //
+    // C: 24-bit binary fraction (mantissa), the binary point is left of the MSB
+    //
+    // value = mantissa / 2^24 * 2^(exponent - 64)
+    //
+    // 0x41800000 = 01000001 10000000 00000000 00000000 -> 1.0
+    // 0xc2a00000 = 11000010 10100000 00000000 00000000 -> -2.5
+    //
+    // Like FP2 the largest magnitudes (all exponent and mantissa bits set) are the special values.
+
+    const F4_POS_INFINITY: u32 = 0x7fffffff;
+    const F4_NEG_INFINITY: u32 = 0xffffffff;
+    const F4_NAN: u32 = 0xfffffffe;
+
+    if data == F4_POS_INFINITY {
+        f64::INFINITY
+    } else if data == F4_NEG_INFINITY {
+        f64::NEG_INFINITY
+    } else if data == F4_NAN {
+        f64::NAN
+    } else {
+        let parts = split_fp4(data);
+        let sign = if parts.negative { - 1.0 } else { 1.0 };
+
+        sign * (parts.mantissa as f64) / 16777216.0 * 2.0f64.powi(parts.exponent as i32 - 64)
+    }
+}
+
 
 
 
-named!(parse_date_time<&[u8], NaiveDateTime >, do_parse!(
+named!(pub parse_date_time<&[u8], NaiveDateTime >, do_parse!(
     seconds: le_u32 >>
     le_u32 >> // unused, since all zero
-    (NaiveDateTime::from_timestamp((seconds + 631152000) as i64, 0))
+    // Seconds since 1990-01-01, a u32 is always in the range of chrono
+    (DateTime::from_timestamp(seconds as i64 + 631152000, 0).map(|date_time| date_time.naive_utc()).unwrap())
     // date_time: 2017-09-13T13:00:00Z, 631152000
     // date_time: 2017-09-13T12:00:00Z, 631148400
 ));
 
-named!(parse_data_simple<&[u8], WeatherStationData>, do_parse!(
-    date_time: parse_date_time >>
-    solar_battery_voltage: be_u16 >> // solar battery voltage
-    lithium_battery_voltage: be_u16 >> // lithium battery valotage
-    wind_direction: be_u16 >> // wind diagnose
-    (
-        WeatherStationData::SimpleData(SimpleDataType {
-            date_time: date_time,
-            solar_battery_voltage: u16_to_f64(solar_battery_voltage),
-            lithium_battery_voltage: u16_to_f64(lithium_battery_voltage),
-            wind_direction: u16_to_f64(wind_direction),
-        })
-    )
-));
+/// Decode one record, bytes must contain at least one record of the definition.
+fn parse_record(bytes: &[u8], record_offset: usize, definition: &RecordDefinition) -> Option<Record> {
+    let date_time = match parse_date_time(bytes) {
+        IResult::Done(_, date_time) => date_time,
+        _ => return None
+    };
+
+    let mut offset = 8;
+    let mut values = Vec::with_capacity(definition.fields.len());
+
+    for field in &definition.fields {
+        let size = field.field_type.size();
+        values.push(field.field_type.decode(&bytes[offset..offset + size]));
+        offset += size;
+    }
+
+    Some(Record {
+        offset: record_offset,
+        date_time,
+        values,
+    })
+}
+
+/// Between 2000 and the time the payload was received, one day later is allowed for a logger clock that is ahead.
+fn plausible_date_time(date_time: NaiveDateTime, received_at: NaiveDateTime) -> bool {
+    let min_date_time = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+    let max_date_time = received_at + Duration::days(1);
+
+    date_time >= min_date_time && date_time <= max_date_time
+}
+
+/// A timestamp in the plausible range with the unused bytes all zero, a record can start here.
+fn plausible_timestamp(bytes: &[u8], received_at: NaiveDateTime) -> bool {
+    match parse_date_time(bytes) {
+        IResult::Done(_, date_time) => plausible_date_time(date_time, received_at) && bytes[4..8] == [0, 0, 0, 0],
+        _ => false
+    }
+}
 
-named!(parse_data_multiple_one<&[u8], MultipleDataType>, do_parse!(
-    date_time: parse_date_time >>
-    air_temperature: be_u16 >>
-    air_relative_humidity: be_u16 >>
-    solar_radiation: be_u16 >>
-    soil_water_content: be_u16 >>
-    soil_temperature: be_u16 >>
-    wind_speed: be_u16 >>
-    wind_max: be_u16 >>
-    wind_direction: be_u16 >>
-    precipitation: be_u16 >>
-    air_pressure: be_u16 >>
-    (
-        MultipleDataType {
-            date_time: date_time,
-            air_temperature: u16_to_f64(air_temperature),
-            air_relative_humidity: u16_to_f64(air_relative_humidity),
-            solar_radiation: u16_to_f64(solar_radiation),
-            soil_water_content: u16_to_f64(soil_water_content),
-            soil_temperature: u16_to_f64(soil_temperature),
-            wind_speed: u16_to_f64(wind_speed),
-            wind_max: u16_to_f64(wind_max),
-            wind_direction: u16_to_f64(wind_direction),
-            precipitation: u16_to_f64(precipitation),
-            air_pressure: u16_to_f64(air_pressure),
+/// All complete records of the definition starting at offset, None if there is not a single one.
+/// With recovery the bytes of a repeated record without a plausible timestamp are skipped
+/// until the next plausible timestamp, see parse_problems() for how they are reported.
+fn parse_records(binary_data: &[u8], offset: usize, definition: &RecordDefinition, recovery: bool,
+        received_at: NaiveDateTime) -> Option<Vec<Record>> {
+    let record_size = definition.record_size();
+    let mut records = Vec::new();
+    let mut offset = offset;
+
+    while offset + record_size <= binary_data.len() {
+        let bytes = &binary_data[offset..offset + record_size];
+
+        if recovery && definition.repeated && !plausible_timestamp(bytes, received_at) {
+            offset += 1;
+            continue;
         }
-    )
-));
 
-named!(parse_data_multiple<&[u8], WeatherStationData>, do_parse!(
-    multiple: many1!(parse_data_multiple_one) >>
-    (
-        WeatherStationData::MultipleData(multiple)
-    )
-));
+        records.push(parse_record(bytes, offset, definition)?);
+        offset += record_size;
 
-named!(multiple_or_simple<&[u8], WeatherStationData>, do_parse!(
-    result: alt!(complete!(parse_data_multiple) | complete!(parse_data_simple)) >>
-    ( result )
-));
+        if !definition.repeated {
+            break;
+        }
+    }
+
+    if records.is_empty() { None } else { Some(records) }
+}
+
+/// The records of one record definition that has at least one complete record in the payload.
+struct Candidate<'a> {
+    definition: &'a RecordDefinition,
+    records: Vec<Record>,
+    /// The records use all bytes of the payload
+    exact: bool,
+}
+
+fn parse_candidate<'a>(binary_data: &[u8], definition: &'a RecordDefinition, recovery: bool,
+        received_at: NaiveDateTime) -> Option<Candidate<'a>> {
+    if !binary_data.starts_with(&definition.header) {
+        return None
+    }
+
+    let header_size = definition.header.len();
+    let records = parse_records(binary_data, header_size, definition, recovery, received_at)?;
+    let exact = header_size + records.len() * definition.record_size() == binary_data.len();
+
+    Some(Candidate {
+        definition,
+        records,
+        exact,
+    })
+}
+
+/// Timestamps before 2000 or after the payload was received and records that are not in chronological order
+/// are a sign that the payload was decoded with the wrong record definition.
+fn plausible_records(records: &[Record], received_at: NaiveDateTime) -> bool {
+    records.iter().all(|record| plausible_date_time(record.date_time, received_at)) &&
+        records.windows(2).all(|pair| pair[0].date_time <= pair[1].date_time)
+}
+
+/// Choose the record definition for the payload, the rules are tried in this order:
+/// only one definition in the layout, a matching header, the only exact length, the only plausible timestamps
+/// and if none of them decides the first definition in the layout.
+fn choose_candidate(mut candidates: Vec<Candidate>, num_of_definitions: usize, received_at: NaiveDateTime) -> Option<(Candidate, Detection)> {
+    if num_of_definitions == 1 {
+        return candidates.pop().map(|candidate| (candidate, Detection::SingleDefinition))
+    }
+
+    if let Some(i) = candidates.iter().position(|candidate| !candidate.definition.header.is_empty()) {
+        return Some((candidates.swap_remove(i), Detection::Header))
+    }
+
+    let exact: Vec<usize> = (0..candidates.len()).filter(|i| candidates[*i].exact).collect();
+
+    if exact.len() == 1 {
+        return Some((candidates.swap_remove(exact[0]), Detection::Length))
+    }
 
-pub fn parse_data(binary_data: Vec<u8>) -> Result<WeatherStationData> {
-    match multiple_or_simple(&binary_data) {
-        IResult::Done(rest, result) => {
-            if rest.len() > 0 {
-                info!("parse rest: {:?}", rest);
+    let matching = if exact.is_empty() { (0..candidates.len()).collect() } else { exact };
+    let plausible: Vec<usize> = matching.iter().cloned().filter(|i| plausible_records(&candidates[*i].records, received_at)).collect();
+
+    if plausible.len() == 1 {
+        return Some((candidates.swap_remove(plausible[0]), Detection::Plausibility))
+    }
+
+    let first = *plausible.first().or_else(|| matching.first())?;
+
+    Some((candidates.swap_remove(first), Detection::LayoutOrder))
+}
+
+/// The problems the strictness of the layout is applied to: NaN in mandatory fields,
+/// bytes skipped by the recovery, an incomplete last record and bytes after the last record.
+fn parse_problems(binary_data: &[u8], data: &WeatherStationData) -> Vec<ErrorKind> {
+    let record_size = data.definition.record_size();
+    let mut used = data.definition.header.len();
+    let mut problems = Vec::new();
+
+    for record in &data.records {
+        if record.offset > used {
+            problems.push(ErrorKind::CorruptedRecord(used, record.offset - used));
+        }
+
+        let mut offset = record.offset + 8;
+
+        for (field, value) in data.definition.fields.iter().zip(&record.values) {
+            if field.mandatory && value.is_nan() {
+                problems.push(ErrorKind::MissingValue(offset, field.name.clone()));
             }
-            Ok(result)
-        },
-        IResult::Error(err) => {
-            bail!("parse error: {:?}", err)
-        },
-        IResult::Incomplete(needed) => {
-            bail!("parse error, more input needed: {:?}", needed)
+            offset += field.field_type.size();
+        }
+
+        used = record.offset + record_size;
+    }
+
+    if used < binary_data.len() {
+        if data.definition.repeated && binary_data.len() - used >= record_size {
+            problems.push(ErrorKind::CorruptedRecord(used, binary_data.len() - used));
+        } else if data.definition.repeated {
+            problems.push(ErrorKind::PartialRecord(used, binary_data.len() - used, record_size));
+        } else {
+            problems.push(ErrorKind::TrailingBytes(used, binary_data.len() - used));
+        }
+    }
+
+    problems
+}
+
+/// Decode the payload with the record definition of the layout chosen by choose_candidate().
+/// received_at: when the payload was received, the same payload is always decoded the same way,
+/// also when it is reprocessed later.
+///
+/// Strict rejects the payload at the first parse problem. With recovery the corrupted records are
+/// skipped on purpose, so strict only rejects the other problems and the complete records are returned.
+/// All problems that did not reject the payload are returned with the records.
+pub fn parse_data(binary_data: &[u8], layout: &RecordLayout, received_at: NaiveDateTime) -> Result<WeatherStationData> {
+    let candidates = layout.records.iter()
+        .filter_map(|definition| parse_candidate(binary_data, definition, layout.recovery, received_at))
+        .collect();
+
+    let (candidate, detection) = match choose_candidate(candidates, layout.records.len(), received_at) {
+        Some(result) => result,
+        None => bail!("parse error: {} bytes do not match any record of layout '{}'", binary_data.len(), layout.name)
+    };
+
+    info!("Record definition '{}' chosen by rule: {}", candidate.definition.name, detection);
+
+    let mut data = WeatherStationData {
+        definition: candidate.definition.clone(),
+        detection,
+        records: candidate.records,
+        problems: Vec::new(),
+    };
+
+    for problem in parse_problems(binary_data, &data) {
+        let recovered = layout.recovery && matches!(problem, ErrorKind::CorruptedRecord(..));
+
+        match layout.strictness {
+            Strictness::Strict if !recovered => return Err(problem.into()),
+            Strictness::Strict | Strictness::Warn => warn!("{}", problem),
+            Strictness::Lenient => info!("{}", problem),
         }
+
+        data.problems.push(problem.to_string());
     }
+
+    Ok(data)
 }
 
 
@@ -189,13 +411,13 @@ pub fn parse_data(binary_data: Vec<u8>) -> Result<WeatherStationData> {
 parser!{
     fn parse_date_time2['a, I]()(I) -> NaiveDateTime where [I: RangeStream<Item = u8, Range = &'a [u8]>,] {
         (num::le_u32(), num::le_u32()).map(|(seconds, _) : (u32, u32)| {
-            NaiveDateTime::from_timestamp((seconds + 631152000) as i64, 0)
+            DateTime::from_timestamp(seconds as i64 + 631152000, 0).map(|date_time| date_time.naive_utc()).unwrap()
         })
     }
 }
 
 parser!{
-    fn parse_data_simple2['a, I]()(I) -> WeatherStationData where [I: RangeStream<Item = u8, Range = &'a [u8]>,] {
+    fn parse_data_simple2['a, I]()(I) -> Record where [I: RangeStream<Item = u8, Range = &'a [u8]>,] {
         (parse_date_time2(),
             num::be_u16(),
             num::be_u16(),
@@ -211,65 +433,289 @@ parser!{
             u16,
             u16)| {
                 
-            WeatherStationData::SimpleData(SimpleDataType {
-                date_time: date_time,
-                solar_battery_voltage: u16_to_f64(solar_battery_voltage),
-                lithium_battery_voltage: u16_to_f64(lithium_battery_voltage),
-                wind_direction: u16_to_f64(wind_direction),
-            })
+            Record {
+                offset: 0,
+                date_time,
+                values: vec![
+                    u16_to_f64(solar_battery_voltage),
+                    u16_to_f64(lithium_battery_voltage),
+                    u16_to_f64(wind_direction),
+                ],
+            }
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{NaiveDateTime};
+    use chrono::{NaiveDate, NaiveDateTime, Duration};
     use combine::{Parser};
 
+    use toml;
+
+    use error::{ErrorKind};
+    use layout::{RecordLayout, Strictness, builtin_layout};
+
     use super::{
-        SimpleDataType,
-        MultipleDataType,
-        WeatherStationData,
+        Record,
+        Detection,
         parse_data,
+        plausible_date_time,
+        fp4_to_f64,
         parse_date_time2,
         parse_data_simple2
     };
+    use test_data::{SIMPLE, MULTIPLE, received_at};
 
     #[test]
     fn test_parse_binary_data_battery1() {
-        let result = parse_data(vec![0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 96, 0, 0, 0]).unwrap();
+        let result = parse_data(&[0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 96, 0, 0, 0], &builtin_layout("simple_and_multiple").unwrap(), received_at()).unwrap();
         let date_time = NaiveDateTime::parse_from_str("2016-09-19 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
-        assert_eq!(result,
-            WeatherStationData::SimpleData(SimpleDataType {
-                date_time: date_time,
-                solar_battery_voltage: 12.76,
-                lithium_battery_voltage: 0.0,
-                wind_direction: 0.0,
-            })
+        assert_eq!(result.definition.name, "simple");
+        assert_eq!(result.detection, Detection::Length);
+        assert_eq!(result.records,
+            vec![Record {
+                offset: 0,
+                date_time,
+                values: vec![12.76, 0.0, 0.0],
+            }]
         );
     }
 
     #[test]
     fn test_parse_binary_data_full1() {
-        let result = parse_data(vec![0, 141, 64, 50, 0, 0, 0, 0, 69, 222, 35, 229, 92, 249, 96, 77, 70, 100, 97, 103, 98, 238, 43, 190, 99, 232, 3, 194]).unwrap();
+        let result = parse_data(&[0, 141, 64, 50, 0, 0, 0, 0, 69, 222, 35, 229, 92, 249, 96, 77, 70, 100, 97, 103, 98, 238, 43, 190, 99, 232, 3, 194], &builtin_layout("simple_and_multiple").unwrap(), received_at()).unwrap();
         let date_time = NaiveDateTime::parse_from_str("2016-09-19 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
-        assert_eq!(result,
-            WeatherStationData::MultipleData(vec![MultipleDataType {
-                date_time: date_time,
-                air_temperature: 15.02,
-                air_relative_humidity: 99.7,
-                solar_radiation: 74.17,
-                soil_water_content: 0.077,
-                soil_temperature: 16.36,
-                wind_speed: 0.359,
-                wind_max: 0.75,
-                wind_direction: 300.6,
-                precipitation: 1.0,
-                air_pressure: 962.0,
-            }])
+        assert_eq!(result.definition.name, "multiple");
+        assert_eq!(result.detection, Detection::Length);
+        assert_eq!(result.records,
+            vec![Record {
+                offset: 0,
+                date_time,
+                // air_temperature, air_relative_humidity, solar_radiation, soil_water_content, soil_temperature,
+                // wind_speed, wind_max, wind_direction, precipitation, air_pressure
+                values: vec![15.02, 99.7, 74.17, 0.077, 16.36, 0.359, 0.75, 300.6, 1.0, 962.0],
+            }]
         );
     }
 
+    #[test]
+    fn test_detection() {
+        let layout = builtin_layout("simple_and_multiple").unwrap();
+
+        // A multiple record followed by a cut off record: no exact length, both are plausible
+        let mut data = MULTIPLE.to_vec();
+        data.extend_from_slice(&SIMPLE);
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!((result.definition.name.as_str(), result.detection), ("multiple", Detection::LayoutOrder));
+
+        let result = parse_data(&SIMPLE, &builtin_layout("simple").unwrap(), received_at()).unwrap();
+        assert_eq!(result.detection, Detection::SingleDefinition);
+
+        // 28 bytes are one record with 10 values or two records with 3 values, the second timestamp is 2126
+        let layout: RecordLayout = toml::from_str(r#"
+            name = "ambiguous"
+
+            [[record]]
+            name = "short"
+            table = "battery_data"
+            repeated = true
+            fields = [
+                { name = "a", type = "FP2", column = "battery_voltage" },
+                { name = "b", type = "FP2", column = "li_battery_voltage" },
+                { name = "c", type = "FP2", column = "wind_dir" },
+            ]
+
+            [[record]]
+            name = "status"
+            table = "battery_data"
+            header = [255]
+            fields = [
+                { name = "a", type = "FP2", column = "battery_voltage" },
+            ]
+
+            [[record]]
+            name = "long"
+            table = "multiple_data"
+            fields = [
+                { name = "a", type = "UINT4", column = "air_temperature" },
+                { name = "b", type = "UINT4", column = "air_relative_humidity" },
+                { name = "c", type = "UINT4", column = "solar_radiation" },
+                { name = "d", type = "UINT4", column = "soil_water_content" },
+                { name = "e", type = "UINT4", column = "soil_temperature" },
+            ]
+        "#).unwrap();
+        layout.validate().unwrap();
+
+        let mut data = MULTIPLE[0..14].to_vec();
+        data.extend_from_slice(&[255, 255, 255, 255, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6]);
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!((result.definition.name.as_str(), result.detection), ("long", Detection::Plausibility));
+
+        let mut data = vec![255];
+        data.extend_from_slice(&MULTIPLE[0..10]);
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!((result.definition.name.as_str(), result.detection), ("status", Detection::Header));
+        assert_eq!(result.records[0].values, vec![15.02]);
+    }
+
+    #[test]
+    fn test_strictness() {
+        let mut layout = builtin_layout("simple_and_multiple").unwrap();
+        layout.strictness = Strictness::Strict;
+
+        assert!(parse_data(&MULTIPLE, &layout, received_at()).is_ok());
+
+        let mut data = MULTIPLE.to_vec();
+        data.extend_from_slice(&MULTIPLE[0..10]);
+        match *parse_data(&data, &layout, received_at()).unwrap_err().kind() {
+            ErrorKind::PartialRecord(28, 10, 28) => {},
+            ref kind => panic!("expected partial record, got: {:?}", kind)
+        }
+
+        let mut data = SIMPLE.to_vec();
+        data.push(0);
+        match *parse_data(&data, &layout, received_at()).unwrap_err().kind() {
+            ErrorKind::TrailingBytes(14, 1) => {},
+            ref kind => panic!("expected trailing bytes, got: {:?}", kind)
+        }
+
+        // FP2 NaN in the lithium battery voltage
+        let mut data = SIMPLE.to_vec();
+        data[10] = 0x9f;
+        data[11] = 0xfe;
+        assert!(parse_data(&data, &layout, received_at()).is_ok());
+
+        layout.records[1].fields[1].mandatory = true;
+        match *parse_data(&data, &layout, received_at()).unwrap_err().kind() {
+            ErrorKind::MissingValue(10, ref field) => assert_eq!(field, "lithium_battery_voltage"),
+            ref kind => panic!("expected missing value, got: {:?}", kind)
+        }
+
+        layout.strictness = Strictness::Warn;
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert!(result.records[0].values[1].is_nan());
+        assert_eq!(result.problems, vec![ErrorKind::MissingValue(10, "lithium_battery_voltage".to_string()).to_string()]);
+
+        layout.strictness = Strictness::Lenient;
+        data.push(0);
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!(result.records.len(), 1);
+        assert_eq!(result.problems.len(), 2);
+    }
+
+    #[test]
+    fn test_plausible_date_time() {
+        let received_at = received_at();
+        assert!(plausible_date_time(received_at - Duration::days(365), received_at));
+        assert!(plausible_date_time(received_at + Duration::hours(23), received_at));
+        assert!(!plausible_date_time(received_at + Duration::days(2), received_at));
+        assert!(!plausible_date_time(NaiveDate::from_ymd_opt(1999, 12, 31).unwrap().and_hms_opt(0, 0, 0).unwrap(), received_at));
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut layout = builtin_layout("multiple").unwrap();
+
+        // A record, 5 corrupted bytes, the record of the next hour and a cut off record
+        let mut next_hour = MULTIPLE;
+        next_hour[0..4].copy_from_slice(&[0x10, 0x9b, 0x40, 0x32]);
+        let mut data = MULTIPLE.to_vec();
+        data.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef, 0x01]);
+        data.extend_from_slice(&next_hour);
+        data.extend_from_slice(&MULTIPLE[0..10]);
+
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!(result.records.len(), 2);
+        assert_eq!(result.records[1].offset, 28);
+
+        layout.recovery = true;
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        let offsets: Vec<usize> = result.records.iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, vec![0, 33]);
+        assert_eq!(result.records[1].date_time, result.records[0].date_time + Duration::hours(1));
+        assert_eq!(result.records[1].values, result.records[0].values);
+        assert_eq!(result.problems, vec![
+            ErrorKind::CorruptedRecord(28, 5).to_string(),
+            ErrorKind::PartialRecord(61, 10, 28).to_string(),
+        ]);
+
+        // Strict with recovery still rejects the cut off record, but not the skipped bytes
+        layout.strictness = Strictness::Strict;
+        match *parse_data(&data, &layout, received_at()).unwrap_err().kind() {
+            ErrorKind::PartialRecord(61, 10, 28) => {},
+            ref kind => panic!("expected partial record, got: {:?}", kind)
+        }
+
+        data.truncate(61);
+        data.extend_from_slice(&[0xff; 30]);
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!(result.records.len(), 2);
+        assert_eq!(result.problems, vec![
+            ErrorKind::CorruptedRecord(28, 5).to_string(),
+            ErrorKind::CorruptedRecord(61, 30).to_string(),
+        ]);
+
+        data.drain(28..33);
+        let result = parse_data(&data, &layout, received_at()).unwrap();
+        assert_eq!(result.records.len(), 2);
+        assert_eq!(result.problems, vec![ErrorKind::CorruptedRecord(56, 30).to_string()]);
+    }
+
+    #[test]
+    fn test_fp4_to_f64() {
+        assert_eq!(fp4_to_f64(0x41800000), 1.0);
+        assert_eq!(fp4_to_f64(0xc2a00000), -2.5);
+        assert_eq!(fp4_to_f64(0x48f0a000), 240.625);
+        assert_eq!(fp4_to_f64(0x00000000), 0.0);
+
+        assert_eq!(fp4_to_f64(0x7fffffff), f64::INFINITY);
+        assert_eq!(fp4_to_f64(0xffffffff), f64::NEG_INFINITY);
+        assert!(fp4_to_f64(0xfffffffe).is_nan());
+        assert!(fp4_to_f64(0x7ffffffe).is_finite());
+    }
+
+    #[test]
+    fn test_parse_mixed_types() {
+        let layout: RecordLayout = toml::from_str(r#"
+            name = "mixed"
+
+            [[record]]
+            name = "status"
+            table = "battery_data"
+            fields = [
+                { name = "battery_voltage", type = "IEEE4", column = "battery_voltage" },
+                { name = "counter", type = "UINT2", column = "li_battery_voltage" },
+                { name = "door_open", type = "BOOL", column = "wind_dir" },
+            ]
+        "#).unwrap();
+
+        let result = parse_data(&[0, 141, 64, 50, 0, 0, 0, 0, 0x41, 0x4c, 0x28, 0xf6, 0x01, 0x2c, 0x01], &layout, received_at()).unwrap();
+        assert_eq!(result.records[0].values, vec![12.760000228881836, 300.0, 1.0]);
+    }
+
+    #[test]
+    fn test_fp4_nan_in_mandatory_field() {
+        let mut layout: RecordLayout = toml::from_str(r#"
+            name = "fp4"
+
+            [[record]]
+            name = "temperature"
+            table = "multiple_data"
+            fields = [
+                { name = "air_temperature", type = "FP4", column = "air_temperature", mandatory = true },
+            ]
+        "#).unwrap();
+        layout.strictness = Strictness::Strict;
+
+        assert_eq!(parse_data(&[0, 141, 64, 50, 0, 0, 0, 0, 0x41, 0x80, 0, 0], &layout, received_at()).unwrap().records[0].values, vec![1.0]);
+
+        match *parse_data(&[0, 141, 64, 50, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xfe], &layout, received_at()).unwrap_err().kind() {
+            ErrorKind::MissingValue(8, ref field) => assert_eq!(field, "air_temperature"),
+            ref kind => panic!("expected missing value, got: {:?}", kind)
+        }
+    }
+
     #[test]
     fn test_parse_date_time2() {
         let input = vec![0, 141, 64, 50, 0, 0, 0, 0];
@@ -285,12 +731,11 @@ mod tests {
         let input = vec![0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 96, 0, 0, 0];
         let rest = vec![];
         let result = parse_data_simple2().parse(input.as_slice());
-        let data_simple = WeatherStationData::SimpleData(SimpleDataType{
+        let data_simple = Record {
+            offset: 0,
             date_time: NaiveDateTime::parse_from_str("2016-09-19 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
-            solar_battery_voltage: 12.76,
-            lithium_battery_voltage: 0.0,
-            wind_direction: 0.0,
-        });
+            values: vec![12.76, 0.0, 0.0],
+        };
 
         assert_eq!(result, Ok((data_simple, rest.as_slice())));
     }
@@ -1,11 +1,23 @@
 // External modules:
+use chrono::{NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
 
-use chrono::{NaiveDateTime};
-use mysql::{OptsBuilder, Pool, from_row, Value};
+// System modules:
+use std::mem;
 
 // Internal modules:
 use error::{Result};
-use data_parser::{WeatherStationData, SimpleDataType, MultipleDataType};
+use data_parser::{Record, WeatherStationData, parse_data};
+use station::{Station};
+use db_config::{DbConfig};
+use mysql_storage::{MySqlStorage};
+use sqlite_storage::{SqliteStorage};
+use postgres_storage::{PostgresStorage};
+use migration::{Migration};
+use email::{SbdMessage};
+use station::{parse_sbd_file_name};
+
+// The tables are created by the migrations in migration.rs, this is the layout in MySQL.
 
 /*
 | id                 | int(10) unsigned | NO   | PRI | NULL    | auto_increment |
@@ -14,6 +26,8 @@ use data_parser::{WeatherStationData, SimpleDataType, MultipleDataType};
 | battery_voltage    | double           | YES  |     | NULL    |                |
 | li_battery_voltage | double           | YES  |     | NULL    |                |
 | wind_dir           | double           | YES  |     | NULL    |                |
+
+UNIQUE KEY station_timestamp (station, timestamp)
 */
 
 /*
@@ -30,195 +44,329 @@ use data_parser::{WeatherStationData, SimpleDataType, MultipleDataType};
 | wind_direction        | double           | YES  |     | NULL    |                |
 | precipitation         | double           | YES  |     | NULL    |                |
 | air_pressure          | double           | YES  |     | NULL    |                |
+
+UNIQUE KEY station_timestamp (station, timestamp)
+*/
+
+/*
+sbd_messages: metadata of the Iridium e-mails, battery_data and multiple_data have
+a column sbd_message_id that links the rows to the message they were imported from.
+
+| id             | int(10) unsigned | NO   | PRI | NULL    | auto_increment |
+| imei           | varchar(15)      | NO   |     | NULL    |                |
+| momsn          | int(10) unsigned | NO   |     | NULL    |                |
+| mtmsn          | int(10) unsigned | YES  |     | NULL    |                |
+| station        | varchar(32)      | YES  |     | NULL    |                |
+| session_time   | datetime         | YES  |     | NULL    |                |
+| session_status | varchar(64)      | YES  |     | NULL    |                |
+| message_size   | int(10) unsigned | YES  |     | NULL    |                |
+| latitude       | double           | YES  |     | NULL    |                |
+| longitude      | double           | YES  |     | NULL    |                |
+| cep_radius     | double           | YES  |     | NULL    |                |
+
+UNIQUE KEY imei_momsn (imei, momsn)
 */
 
-fn get_id_from_datetime(db_pool: &Pool, table_name: &str, station_name: &str, datetime: NaiveDateTime) -> Result<Option<u32>> {
-    // select id, battery_voltage from battery_data where timestamp = '2017-10-05 00:00:00' and station = 'Santa_Gracia';
-    let query = format!("SELECT id FROM {} WHERE timestamp = '{}' and station = '{}'", table_name, datetime, station_name);
-    // let rows = db_pool.prep_exec(query, ())?.map(|row: std::result::Result<Row, mysql::Error>| from_row(row?));
+/*
+raw_messages: the original bytes of every imported or skipped SBD payload, so they can be parsed again.
 
-    let mut result: Vec<(u32,)> = Vec::new();
+| id             | int(10) unsigned | NO   | PRI | NULL    | auto_increment |
+| sha256         | char(64)         | NO   | UNI | NULL    |                |
+| imei           | varchar(15)      | YES  |     | NULL    |                |
+| momsn          | int(10) unsigned | YES  |     | NULL    |                |
+| station        | varchar(32)      | YES  |     | NULL    |                | NULL: unknown station
+| file_name      | varchar(255)     | NO   |     | NULL    |                |
+| received_at    | datetime         | NO   |     | NULL    |                |
+| payload        | blob             | NO   |     | NULL    |                |
+| parse_status   | varchar(16)      | NO   |     | NULL    |                | ok, failed or skipped
+| parse_error    | text             | YES  |     | NULL    |                |
+| parsed_at      | datetime         | NO   |     | NULL    |                |
+| sbd_message_id | int(10) unsigned | YES  | MUL | NULL    |                |
+*/
 
-    for row in db_pool.prep_exec(query, ())? {
-        result.push(from_row(row?));
+/// The tables the importer writes to, table names are never taken from user input.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Table {
+    BatteryData,
+    MultipleData,
+}
+
+impl Table {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Table::BatteryData => "battery_data",
+            Table::MultipleData => "multiple_data",
+        }
     }
 
-    if result.len() == 0 {
-        info!("no entry found with datetime: {}", datetime);
-        Ok(None)
-    } else if result.len() == 1 {
-        let (id,) = result[0];
-        info!("id from database: {}, datetime: {}", id, datetime);
-        Ok(Some(id))
-    } else {
-        bail!("expected exactly one id from database but got: {:?}", result);
+    /// The value columns created by the migrations, a record layout can only write to these.
+    pub fn columns(&self) -> &'static [&'static str] {
+        match *self {
+            Table::BatteryData => &["battery_voltage", "li_battery_voltage", "wind_dir"],
+            Table::MultipleData => &["air_temperature", "air_relative_humidity", "solar_radiation", "soil_water_content",
+                "soil_temperature", "wind_speed", "wind_max", "wind_direction", "precipitation", "air_pressure"],
+        }
     }
 }
 
-fn import_simple(db_pool: Pool, station_name: &str, data: SimpleDataType) -> Result<()> {
-    match get_id_from_datetime(&db_pool, "battery_data", station_name, data.date_time)? {
-        Some(id) => {
-            let query = format!("UPDATE battery_data SET
-                timestamp = :timestamp,
-                station = :station,
-                battery_voltage = :battery_voltage,
-                li_battery_voltage = :li_battery_voltage,
-                wind_dir = :wind_dir
-            WHERE id = '{}'", id);
-
-            info!("query: '{}'", query);
-
-            db_pool.prep_exec(query, (
-                Value::from(data.date_time),
-                Value::from(station_name),
-                Value::from(data.solar_battery_voltage),
-                Value::from(data.lithium_battery_voltage),
-                Value::from(data.wind_direction)
-            ))?;
-
-            info!("single with id");
-        },
-        None => {
-            let query = format!("INSERT INTO battery_data (
-                timestamp,
-                station,
-                battery_voltage,
-                li_battery_voltage,
-                wind_dir
-            ) VALUES (
-                :timestamp,
-                :station,
-                :battery_voltage,
-                :li_battery_voltage,
-                :wind_dir
-            )");
-
-            info!("query: '{}'", query);
-
-            db_pool.prep_exec(query, (
-                Value::from(data.date_time),
-                Value::from(station_name),
-                Value::from(data.solar_battery_voltage),
-                Value::from(data.lithium_battery_voltage),
-                Value::from(data.wind_direction)
-            ))?;
-
-            info!("single without id");
+/// An SBD payload as it was received, identified by the SHA-256 of its bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawMessage {
+    pub sha256: String,
+    pub imei: Option<String>,
+    pub momsn: Option<u32>,
+    /// None if the station is unknown, see archive_skipped()
+    pub station: Option<String>,
+    pub file_name: String,
+    /// UTC
+    pub received_at: NaiveDateTime,
+    pub payload: Vec<u8>,
+}
+
+impl RawMessage {
+    /// IMEI and MOMSN are taken from the e-mail or DirectIP metadata if there is any, otherwise from the file name.
+    pub fn new(file_name: &str, station_name: Option<&str>, payload: Vec<u8>, sbd_message: Option<&SbdMessage>) -> RawMessage {
+        let (imei, momsn) = match sbd_message {
+            Some(sbd_message) => (Some(sbd_message.imei.clone()), Some(sbd_message.momsn)),
+            None => match parse_sbd_file_name(file_name) {
+                Some(sbd_file_name) => (Some(sbd_file_name.imei), Some(sbd_file_name.momsn)),
+                None => (None, None),
+            }
+        };
+
+        RawMessage {
+            sha256: sha256_hex(&payload),
+            imei,
+            momsn,
+            station: station_name.map(str::to_string),
+            file_name: file_name.to_string(),
+            received_at: Utc::now().naive_utc(),
+            payload,
         }
     }
+}
 
-    Ok(())
-}
-
-fn import_multiple(db_pool: Pool, station_name: &str, data: Vec<MultipleDataType>) -> Result<()> {
-    for data in data {
-        match get_id_from_datetime(&db_pool, "multiple_data", station_name, data.date_time)? {
-            Some(id) => {
-                let query = format!("UPDATE multiple_data SET
-                    timestamp = :timestamp,
-                    station = :station,
-                    air_temperature = :air_temperature,
-                    air_relative_humidity = :air_relative_humidity,
-                    solar_radiation = :solar_radiation,
-                    soil_water_content = :soil_water_content,
-                    soil_temperature = :soil_temperature,
-                    wind_speed = :wind_speed,
-                    wind_max = :wind_max,
-                    wind_direction = :wind_direction,
-                    precipitation = :precipitation,
-                    air_pressure = :air_pressure
-                WHERE id = '{}'", id);
-
-                info!("query: '{}'", query);
-
-                db_pool.prep_exec(query, (
-                    Value::from(data.date_time),
-                    Value::from(station_name),
-                    Value::from(data.air_temperature),
-                    Value::from(data.air_relative_humidity),
-                    Value::from(data.solar_radiation),
-                    Value::from(data.soil_water_content),
-                    Value::from(data.soil_temperature),
-                    Value::from(data.wind_speed),
-                    Value::from(data.wind_max),
-                    Value::from(data.wind_direction),
-                    Value::from(data.precipitation),
-                    Value::from(data.air_pressure)
-                ))?;
-
-                info!("multiple with id");
-            },
-            None => {
-                let query = format!("INSERT INTO multiple_data (
-                    timestamp,
-                    station,
-                    air_temperature,
-                    air_relative_humidity,
-                    solar_radiation,
-                    soil_water_content,
-                    soil_temperature,
-                    wind_speed,
-                    wind_max,
-                    wind_direction,
-                    precipitation,
-                    air_pressure
-                ) VALUES (
-                    :timestamp,
-                    :station,
-                    :air_temperature,
-                    :air_relative_humidity,
-                    :solar_radiation,
-                    :soil_water_content,
-                    :soil_temperature,
-                    :wind_speed,
-                    :wind_max,
-                    :wind_direction,
-                    :precipitation,
-                    :air_pressure
-                )");
-
-                info!("query: '{}'", query);
-
-                db_pool.prep_exec(query, (
-                    Value::from(data.date_time),
-                    Value::from(station_name),
-                    Value::from(data.air_temperature),
-                    Value::from(data.air_relative_humidity),
-                    Value::from(data.solar_radiation),
-                    Value::from(data.soil_water_content),
-                    Value::from(data.soil_temperature),
-                    Value::from(data.wind_speed),
-                    Value::from(data.wind_max),
-                    Value::from(data.wind_direction),
-                    Value::from(data.precipitation),
-                    Value::from(data.air_pressure)
-                ))?;
-
-                info!("multiple without id");
-            }
+/// What happened to a raw message when it was parsed the last time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseStatus {
+    /// Parsed and the records written
+    Ok,
+    /// The payload could not be parsed
+    Failed,
+    /// Not parsed, e.g. the station is unknown or the payload is empty
+    Skipped,
+}
+
+impl ParseStatus {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ParseStatus::Ok => "ok",
+            ParseStatus::Failed => "failed",
+            ParseStatus::Skipped => "skipped",
         }
     }
+}
+
+/// A raw message read back from the database for reprocessing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRawMessage {
+    pub id: u64,
+    pub sbd_message_id: Option<u64>,
+    pub raw_message: RawMessage,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Number of rows written by one import.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImportCount {
+    pub inserted: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+}
+
+/// A database the weatherstation data can be written to.
+pub trait Storage {
+    /// The version of the last migration applied, 0 for an empty database.
+    /// Nothing is written, the schema_version table is created by the first migration.
+    fn schema_version(&mut self) -> Result<u32>;
+
+    /// Run the SQL of the migration for this backend and record its version.
+    fn apply_migration(&mut self, migration: &Migration) -> Result<()>;
+
+    /// The id of the record of the station with the given timestamp, if there is one.
+    fn get_id(&mut self, table: Table, station_name: &str, date_time: NaiveDateTime) -> Result<Option<u64>>;
+
+    /// The stored values of the given columns of the record of the station with the given timestamp, if there is one.
+    /// The column names must come from a validated record layout, see RecordLayout::validate().
+    fn get_values(&mut self, table: Table, columns: &[&str], station_name: &str, date_time: NaiveDateTime) -> Result<Option<Vec<Option<f64>>>>;
+
+    /// True if a payload with this hash was imported and parsed before.
+    fn is_imported(&mut self, sha256: &str) -> Result<bool>;
+
+    /// The stored raw messages in the order they were received, optionally only those that could not be parsed
+    /// or were skipped, or only those of one station.
+    fn raw_messages(&mut self, failed_only: bool, station_name: Option<&str>) -> Result<Vec<StoredRawMessage>>;
+
+    /// Start a transaction, all records are written through it.
+    fn transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>>;
+}
+
+/// Insert or update records, nothing is visible to others before commit() is called.
+pub trait StorageTransaction {
+    /// Insert or update the records (unique by station and timestamp), the values are in the order of the columns.
+    /// The column names must come from a validated record layout, see RecordLayout::validate().
+    fn upsert_records(&mut self, table: Table, station_name: &str, columns: &[&str], records: &[Record]) -> Result<ImportCount>;
+
+    /// Insert or update the metadata of the message (unique by IMEI and MOMSN) and return its id.
+    fn upsert_sbd_message(&mut self, station_name: &str, message: &SbdMessage) -> Result<u64>;
+
+    /// Set sbd_message_id of the records of the station with the given timestamps.
+    fn link_sbd_message(&mut self, table: Table, station_name: &str, date_times: &[NaiveDateTime], sbd_message_id: u64) -> Result<()>;
+
+    /// Insert the raw message or update the parse status if the hash is already stored,
+    /// the station is only set if it was unknown before.
+    /// parse_error: why the payload could not be parsed or was skipped.
+    fn upsert_raw_message(&mut self, raw_message: &RawMessage, sbd_message_id: Option<u64>, parse_status: ParseStatus,
+        parse_error: Option<&str>) -> Result<()>;
+
+    fn commit(self: Box<Self>) -> Result<()>;
+
+    fn rollback(self: Box<Self>) -> Result<()>;
+}
+
+/// Open the storage backend given in the config.
+/// read_only: for --dry_run, a SQLite database file is neither created nor written.
+pub fn open_storage(db_config: &DbConfig, db_password: Option<&str>, read_only: bool) -> Result<Box<dyn Storage>> {
+    match db_config.backend.as_str() {
+        "mysql" => Ok(Box::new(MySqlStorage::connect(db_config, db_password)?)),
+        "sqlite" if read_only => Ok(Box::new(SqliteStorage::open_read_only(&db_config.name)?)),
+        "sqlite" => Ok(Box::new(SqliteStorage::open(&db_config.name)?)),
+        "postgres" => Ok(Box::new(PostgresStorage::connect(db_config, db_password)?)),
+        backend => bail!("Unknown database backend: '{}', expected mysql, sqlite or postgres", backend)
+    }
+}
+
+/// Write the records and link them to the message, if there is one.
+fn write_records(db_tx: &mut dyn StorageTransaction, station: &Station, data: WeatherStationData,
+        sbd_message_id: Option<u64>) -> Result<ImportCount> {
+    let table = data.definition.table;
+    let import_count = db_tx.upsert_records(table, &station.name, &data.definition.columns(), &data.records)?;
 
-    Ok(())
+    if let Some(sbd_message_id) = sbd_message_id {
+        let date_times: Vec<NaiveDateTime> = data.records.iter().map(|record| record.date_time).collect();
+        db_tx.link_sbd_message(table, &station.name, &date_times, sbd_message_id)?;
+    }
+
+    Ok(import_count)
 }
 
-pub fn import_to_db(db_user: &str, db_password: &str, station_name: &str, data: WeatherStationData) -> Result<()> {
-    let mut db_builder = OptsBuilder::new();
-    db_builder.ip_or_hostname(Some("localhost"))
-        .db_name(Some("weatherstation"))
-        .user(Some(db_user))
-        .pass(Some(db_password));
+/// Parse the payload with the current parser, write its records and archive it with the parse status.
+/// The parse problems that did not reject the payload are returned with the count.
+/// A payload that can not be parsed is archived anyway, the parse error is returned with the (empty) count.
+fn write_raw_message(db_tx: &mut dyn StorageTransaction, station: &Station, raw_message: &RawMessage,
+        sbd_message: Option<&SbdMessage>, sbd_message_id: Option<u64>) -> Result<(ImportCount, Vec<String>, Option<String>)> {
+    let sbd_message_id = match sbd_message {
+        Some(sbd_message) => Some(db_tx.upsert_sbd_message(&station.name, sbd_message)?),
+        None => sbd_message_id,
+    };
 
-    let db_pool = Pool::new(db_builder)?;
+    let (import_count, problems, parse_error) = match parse_data(&raw_message.payload, &station.layout, raw_message.received_at) {
+        Ok(mut data) => {
+            let problems = mem::take(&mut data.problems);
+            (write_records(db_tx, station, data, sbd_message_id)?, problems, None)
+        },
+        Err(e) => (ImportCount::default(), Vec::new(), Some(e.to_string())),
+    };
 
-    info!("Connected to database");
+    let parse_status = if parse_error.is_some() { ParseStatus::Failed } else { ParseStatus::Ok };
+    db_tx.upsert_raw_message(raw_message, sbd_message_id, parse_status, parse_error.as_deref())?;
 
-    match data {
-        WeatherStationData::SimpleData(data) => {
-            import_simple(db_pool, station_name, data)
+    Ok((import_count, problems, parse_error))
+}
+
+/// Commit if everything was written, otherwise roll back.
+fn finish(db_tx: Box<dyn StorageTransaction + '_>, result: Result<ImportCount>) -> Result<ImportCount> {
+    match result {
+        Ok(import_count) => {
+            db_tx.commit()?;
+            info!("Transaction committed, inserted: {}, updated: {}, unchanged: {}",
+                import_count.inserted, import_count.updated, import_count.unchanged);
+            Ok(import_count)
         },
-        WeatherStationData::MultipleData(data) => {
-            import_multiple(db_pool, station_name, data)
+        Err(e) => {
+            // If the connection is lost the server discards the transaction anyway
+            if let Err(rollback_error) = db_tx.rollback() {
+                error!("Rollback failed: {}", rollback_error);
+            }
+            error!("Transaction rolled back, nothing imported: {}", e);
+            Err(e)
         }
     }
 }
+
+/// Import all records of one SBD file in a single transaction.
+/// If any of the records fails nothing is written.
+pub fn import_to_db(storage: &mut dyn Storage, station: &Station, data: WeatherStationData) -> Result<ImportCount> {
+    let mut db_tx = storage.transaction()?;
+    let result = write_records(&mut *db_tx, station, data, None);
+
+    finish(db_tx, result)
+}
+
+fn import_raw(storage: &mut dyn Storage, station: &Station, raw_message: &RawMessage,
+        sbd_message: Option<&SbdMessage>, sbd_message_id: Option<u64>) -> Result<(ImportCount, Vec<String>)> {
+    let mut db_tx = storage.transaction()?;
+    let mut problems = Vec::new();
+    let mut parse_error = None;
+
+    let result = write_raw_message(&mut *db_tx, station, raw_message, sbd_message, sbd_message_id)
+        .map(|(import_count, parse_problems, error)| {
+            problems = parse_problems;
+            parse_error = error;
+            import_count
+        });
+
+    let import_count = finish(db_tx, result)?;
+
+    match parse_error {
+        Some(e) => bail!("{}, payload archived with sha256: {}", e, raw_message.sha256),
+        None => Ok((import_count, problems))
+    }
+}
+
+/// Parse and import the payload, archive it in raw_messages and store the metadata of the Iridium message if there is any,
+/// all in a single transaction. The records are linked to the row in sbd_messages.
+/// Returns the parse problems that did not reject the payload with the count.
+pub fn import_raw_to_db(storage: &mut dyn Storage, station: &Station, raw_message: &RawMessage,
+        sbd_message: Option<&SbdMessage>) -> Result<(ImportCount, Vec<String>)> {
+    import_raw(storage, station, raw_message, sbd_message, None)
+}
+
+/// Parse a stored payload again with the current parser and update its records and parse status.
+/// A payload skipped because its station was unknown gets the station now.
+pub fn reprocess_raw_message(storage: &mut dyn Storage, station: &Station, stored: &StoredRawMessage) -> Result<(ImportCount, Vec<String>)> {
+    let raw_message = RawMessage {
+        station: Some(station.name.clone()),
+        ..stored.raw_message.clone()
+    };
+
+    import_raw(storage, station, &raw_message, None, stored.sbd_message_id)
+}
+
+/// Archive a payload that is not imported, e.g. of a station that is not in the config file yet,
+/// so it can be imported later with reprocess. A payload that was imported before is left as it is.
+pub fn archive_skipped(storage: &mut dyn Storage, raw_message: &RawMessage, reason: &str) -> Result<()> {
+    if storage.is_imported(&raw_message.sha256)? {
+        return Ok(())
+    }
+
+    let mut db_tx = storage.transaction()?;
+    let result = db_tx.upsert_raw_message(raw_message, None, ParseStatus::Skipped, Some(reason))
+        .map(|_| ImportCount::default());
+
+    finish(db_tx, result).map(|_| ())
+}
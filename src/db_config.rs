@@ -0,0 +1,176 @@
+// External modules:
+use toml;
+
+// System modules:
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+// Internal modules:
+use error::{Result, ResultExt};
+
+/// Connection and import settings for the database.
+/// Every setting can be given in the [database] section of the config file,
+/// as environment variable SBD_DB_<KEY> or as command line option --db_<key>.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DbConfig {
+    pub backend: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub name: String,
+    pub socket: Option<String>,
+    pub user: Option<String>,
+    pub password_file: Option<String>,
+    pub option_file: Option<String>,
+    pub tls: bool,
+    pub tls_ca: Option<String>,
+    pub tls_skip_domain_validation: bool,
+    /// 1 to MAX_BATCH_SIZE
+    pub batch_size: usize,
+}
+
+/// MySQL allows at most 65535 placeholders in one statement and a row of multiple_data has 12:
+/// timestamp, station and the 10 values.
+pub const MAX_BATCH_SIZE: usize = 65535 / 12;
+
+#[derive(Debug, Default, Deserialize)]
+struct DbConfigFile {
+    database: Option<DbConfig>,
+}
+
+impl Default for DbConfig {
+    fn default() -> DbConfig {
+        DbConfig {
+            backend: "mysql".to_string(),
+            host: "localhost".to_string(),
+            port: None,
+            name: "weatherstation".to_string(),
+            socket: None,
+            user: None,
+            password_file: None,
+            option_file: None,
+            tls: false,
+            tls_ca: None,
+            tls_skip_domain_validation: false,
+            batch_size: 100,
+        }
+    }
+}
+
+impl DbConfig {
+    pub const KEYS: [&'static str; 12] = ["backend", "host", "port", "name", "socket", "user", "password_file", "option_file",
+        "tls", "tls_ca", "tls_skip_domain_validation", "batch_size"];
+
+    pub fn from_toml(content: &str) -> Result<DbConfig> {
+        let config_file: DbConfigFile = toml::from_str(content)?;
+        Ok(config_file.database.unwrap_or_default())
+    }
+
+    pub fn load(file_name: &str) -> Result<DbConfig> {
+        let mut content = String::new();
+        File::open(file_name)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .chain_err(|| format!("Could not read config file: '{}'", file_name))?;
+
+        DbConfig::from_toml(&content).chain_err(|| format!("Invalid database section in config file: '{}'", file_name))
+    }
+
+    /// Override the settings with the values returned by the lookup function.
+    /// The lookup function is called once for each of the keys in DbConfig::KEYS.
+    pub fn override_from<F>(&mut self, lookup: F) -> Result<()> where F: Fn(&str) -> Option<String> {
+        for key in DbConfig::KEYS.iter() {
+            if let Some(value) = lookup(key) {
+                match *key {
+                    "backend" => self.backend = value,
+                    "host" => self.host = value,
+                    "port" => self.port = value.parse().map(Some).chain_err(|| format!("Invalid database port: '{}'", value))?,
+                    "name" => self.name = value,
+                    "socket" => self.socket = Some(value),
+                    "user" => self.user = Some(value),
+                    "password_file" => self.password_file = Some(value),
+                    "option_file" => self.option_file = Some(value),
+                    "tls" => self.tls = parse_bool(&value)?,
+                    "tls_ca" => self.tls_ca = Some(value),
+                    "tls_skip_domain_validation" => self.tls_skip_domain_validation = parse_bool(&value)?,
+                    "batch_size" => self.batch_size = value.parse().chain_err(|| format!("Invalid batch size: '{}'", value))?,
+                    _ => unreachable!()
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn override_from_env(&mut self) -> Result<()> {
+        self.override_from(|key| env::var(format!("SBD_DB_{}", key.to_uppercase())).ok())
+    }
+
+    /// Check the settings after all overrides are applied.
+    pub fn validate(&self) -> Result<()> {
+        if self.batch_size == 0 || self.batch_size > MAX_BATCH_SIZE {
+            bail!("Invalid batch size: {}, expected 1 to {}", self.batch_size, MAX_BATCH_SIZE);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => bail!("Invalid boolean value: '{}', expected true or false", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{DbConfig, MAX_BATCH_SIZE};
+
+    #[test]
+    fn test_db_config_override() {
+        let mut db_config = DbConfig::from_toml(r#"
+            [database]
+            host = "db.staging"
+            port = 3307
+            user = "sbd_import"
+        "#).unwrap();
+
+        assert_eq!(db_config.name, "weatherstation");
+        assert_eq!(db_config.port, Some(3307));
+
+        let mut values = HashMap::new();
+        values.insert("port", "3308");
+        values.insert("tls", "true");
+        values.insert("tls_ca", "/etc/mysql/ca.pem");
+
+        db_config.override_from(|key| values.get(key).map(|value| value.to_string())).unwrap();
+
+        assert_eq!(db_config.host, "db.staging");
+        assert_eq!(db_config.port, Some(3308));
+        assert_eq!(db_config.user, Some("sbd_import".to_string()));
+        assert!(db_config.tls);
+        assert_eq!(db_config.tls_ca, Some("/etc/mysql/ca.pem".to_string()));
+
+        values.insert("port", "not_a_port");
+        assert!(db_config.override_from(|key| values.get(key).map(|value| value.to_string())).is_err());
+    }
+
+    #[test]
+    fn test_db_config_batch_size() {
+        let mut db_config = DbConfig::default();
+        db_config.validate().unwrap();
+
+        db_config.batch_size = 0;
+        assert!(db_config.validate().is_err());
+
+        db_config.batch_size = MAX_BATCH_SIZE;
+        db_config.validate().unwrap();
+
+        db_config.batch_size = MAX_BATCH_SIZE + 1;
+        assert!(db_config.validate().is_err());
+    }
+}
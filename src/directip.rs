@@ -0,0 +1,424 @@
+// External modules:
+use chrono::{DateTime, Local};
+use nom::{be_u8, be_u16, be_u32, IResult};
+
+// System modules:
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path};
+use std::time::Duration;
+
+// Internal modules:
+use error::{Result, ResultExt};
+use database::{Storage};
+use batch::{FileResult, import_data};
+use email::{SbdMessage};
+use station::{StationRegistry};
+use watch::{connect};
+
+// An Iridium DirectIP MO message starts with the protocol revision (1 byte) and the length
+// of the rest of the message (2 bytes), followed by information elements (IE):
+// IEI (1 byte), length (2 bytes) and the content. All numbers are big endian.
+
+const PROTOCOL_REVISION: u8 = 1;
+
+const IEI_MO_HEADER: u8 = 0x01;
+const IEI_MO_PAYLOAD: u8 = 0x02;
+const IEI_MO_LOCATION: u8 = 0x03;
+const IEI_MO_CONFIRMATION: u8 = 0x05;
+
+/// Time to wait for the rest of a message before the connection is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An MO message received from the Iridium gateway.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectIpMessage {
+    /// Call detail record reference of the gateway
+    pub cdr_reference: u32,
+    pub sbd_message: SbdMessage,
+    pub payload: Vec<u8>,
+}
+
+impl DirectIpMessage {
+    /// The name the payload would have as an e-mail attachment, used to find the station.
+    pub fn file_name(&self) -> String {
+        format!("{}_{:06}.sbd", self.sbd_message.imei, self.sbd_message.momsn)
+    }
+}
+
+struct MoHeader {
+    cdr_reference: u32,
+    imei: String,
+    session_status: u8,
+    momsn: u16,
+    mtmsn: u16,
+    session_time: u32,
+}
+
+struct MoLocation {
+    latitude: f64,
+    longitude: f64,
+    cep_radius: u32,
+}
+
+named!(information_element<&[u8], (u8, &[u8])>, do_parse!(
+    iei: be_u8 >>
+    content: length_bytes!(be_u16) >>
+    ( (iei, content) )
+));
+
+named!(mo_header<&[u8], MoHeader>, do_parse!(
+    cdr_reference: be_u32 >>
+    imei: take!(15) >>
+    session_status: be_u8 >>
+    momsn: be_u16 >>
+    mtmsn: be_u16 >>
+    session_time: be_u32 >> // seconds since 1970-01-01
+    (
+        MoHeader {
+            cdr_reference,
+            imei: String::from_utf8_lossy(imei).into_owned(),
+            session_status,
+            momsn,
+            mtmsn,
+            session_time,
+        }
+    )
+));
+
+// Flags: bit 1 set for south, bit 0 set for west, the minutes are given in thousandths.
+named!(mo_location<&[u8], MoLocation>, do_parse!(
+    flags: be_u8 >>
+    latitude_degrees: be_u8 >>
+    latitude_minutes: be_u16 >>
+    longitude_degrees: be_u8 >>
+    longitude_minutes: be_u16 >>
+    cep_radius: be_u32 >>
+    (
+        MoLocation {
+            latitude: (f64::from(latitude_degrees) + f64::from(latitude_minutes) / 60000.0) * if flags & 0x02 != 0 { -1.0 } else { 1.0 },
+            longitude: (f64::from(longitude_degrees) + f64::from(longitude_minutes) / 60000.0) * if flags & 0x01 != 0 { -1.0 } else { 1.0 },
+            cep_radius,
+        }
+    )
+));
+
+/// The text the Iridium e-mails use for the session status.
+fn session_status_text(session_status: u8) -> String {
+    let description = match session_status {
+        0 => "Transfer OK",
+        1 => "Transfer OK, MT message too large",
+        2 => "Transfer OK, location unacceptable quality",
+        10 => "SBD session timed out",
+        12 => "MO message too large",
+        13 => "RF link loss",
+        14 => "IMEI protocol anomaly",
+        15 => "IMEI prohibited from accessing GSS",
+        _ => "Unknown status",
+    };
+
+    format!("{:02} - {}", session_status, description)
+}
+
+fn parse_ie<'a, T>(iei: u8, content: &'a [u8], parser: fn(&'a [u8]) -> IResult<&'a [u8], T>) -> Result<T> {
+    match parser(content) {
+        IResult::Done(_, result) => Ok(result),
+        _ => bail!("Invalid information element {:#04x}, length: {}", iei, content.len())
+    }
+}
+
+/// Decode a complete MO message, the header IE is required, location and payload are optional.
+pub fn decode_message(raw: &[u8]) -> Result<DirectIpMessage> {
+    if raw.len() < 3 {
+        bail!("DirectIP message too short: {} bytes", raw.len());
+    }
+
+    if raw[0] != PROTOCOL_REVISION {
+        bail!("Unsupported DirectIP protocol revision: {}", raw[0]);
+    }
+
+    let length = usize::from(u16::from(raw[1]) << 8 | u16::from(raw[2]));
+
+    if raw.len() != length + 3 {
+        bail!("DirectIP message length mismatch, expected: {}, got: {}", length, raw.len() - 3);
+    }
+
+    let mut header = None;
+    let mut location = None;
+    let mut payload = Vec::new();
+    let mut rest = &raw[3..];
+
+    while !rest.is_empty() {
+        let (iei, content) = match information_element(rest) {
+            IResult::Done(remaining, result) => {
+                rest = remaining;
+                result
+            },
+            _ => bail!("Truncated information element at offset {}", raw.len() - rest.len())
+        };
+
+        match iei {
+            IEI_MO_HEADER => header = Some(parse_ie(iei, content, mo_header)?),
+            IEI_MO_LOCATION => location = Some(parse_ie(iei, content, mo_location)?),
+            IEI_MO_PAYLOAD => payload = content.to_vec(),
+            _ => info!("Ignoring information element {:#04x}, length: {}", iei, content.len()),
+        }
+    }
+
+    let header = match header {
+        Some(header) => header,
+        None => bail!("DirectIP message without MO header")
+    };
+
+    let sbd_message = SbdMessage {
+        imei: header.imei,
+        momsn: u32::from(header.momsn),
+        mtmsn: Some(u32::from(header.mtmsn)),
+        session_time: DateTime::from_timestamp(i64::from(header.session_time), 0).map(|date_time| date_time.naive_utc()),
+        session_status: Some(session_status_text(header.session_status)),
+        message_size: Some(payload.len() as u32),
+        latitude: location.as_ref().map(|location| location.latitude),
+        longitude: location.as_ref().map(|location| location.longitude),
+        cep_radius: location.as_ref().map(|location| f64::from(location.cep_radius)),
+    };
+
+    Ok(DirectIpMessage {
+        cdr_reference: header.cdr_reference,
+        sbd_message,
+        payload,
+    })
+}
+
+/// The MO confirmation sent back to the gateway after a message was received.
+pub fn confirmation(success: bool) -> Vec<u8> {
+    vec![PROTOCOL_REVISION, 0, 4, IEI_MO_CONFIRMATION, 0, 1, success as u8]
+}
+
+/// Read one message (protocol revision, length and information elements) from the connection.
+pub fn read_message<R: Read>(input: &mut R) -> Result<Vec<u8>> {
+    let mut raw = vec![0; 3];
+    input.read_exact(&mut raw).chain_err(|| "Could not read DirectIP message header")?;
+
+    let length = usize::from(u16::from(raw[1]) << 8 | u16::from(raw[2]));
+    raw.resize(length + 3, 0);
+    input.read_exact(&mut raw[3..]).chain_err(|| format!("Could not read DirectIP message of {} bytes", length))?;
+
+    Ok(raw)
+}
+
+/// Save the raw message, so it can be sent again with directip-replay.
+fn record_message(record_dir: &Path, raw: &[u8]) -> Result<()> {
+    fs::create_dir_all(record_dir).chain_err(|| format!("Could not create directory: '{}'", record_dir.display()))?;
+
+    let file_name = record_dir.join(Local::now().format("%Y%m%d_%H%M%S_%f.dip").to_string());
+
+    File::create(&file_name)
+        .and_then(|mut file| file.write_all(raw))
+        .chain_err(|| format!("Could not write message: '{}'", file_name.display()))
+}
+
+/// Receive one message, import its payload and send the confirmation.
+/// The confirmation reports success only if the payload is stored: imported now or before,
+/// or archived as skipped, see import_data(). It is negative if the message could not be
+/// recorded or decoded or if its import failed.
+pub fn handle_connection(stream: &mut TcpStream, storage: &mut dyn Storage, station_registry: &StationRegistry,
+        record_dir: Option<&Path>) -> Result<FileResult> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let raw = read_message(stream)?;
+
+    if let Some(record_dir) = record_dir {
+        if let Err(e) = record_message(record_dir, &raw) {
+            stream.write_all(&confirmation(false))?;
+            return Err(e)
+        }
+    }
+
+    let message = match decode_message(&raw) {
+        Ok(message) => message,
+        Err(e) => {
+            stream.write_all(&confirmation(false))?;
+            return Err(e)
+        }
+    };
+
+    info!("DirectIP message: IMEI: {}, MOMSN: {}, CDR reference: {}, payload: {} bytes",
+        message.sbd_message.imei, message.sbd_message.momsn, message.cdr_reference, message.payload.len());
+
+    let file_name = message.file_name();
+    let result = import_data(storage, station_registry, &file_name, message.payload, Some(&message.sbd_message), None, false);
+
+    let success = !matches!(result, FileResult::Failed(_));
+
+    stream.write_all(&confirmation(success))?;
+
+    Ok(result)
+}
+
+/// Listen for DirectIP connections from the Iridium gateway, one message per connection.
+pub fn directip_server<F>(address: &str, station_registry: &StationRegistry, record_dir: Option<&Path>, open_storage: F) -> Result<()>
+        where F: Fn() -> Result<Box<dyn Storage>> {
+    let listener = TcpListener::bind(address).chain_err(|| format!("Could not listen on: '{}'", address))?;
+
+    info!("DirectIP server listening on: {}", address);
+
+    let mut storage = None;
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default();
+        let db_storage = storage.get_or_insert_with(|| connect(&open_storage));
+
+        match handle_connection(&mut stream, &mut **db_storage, station_registry, record_dir) {
+            Ok(FileResult::Imported(import_count, problems)) => {
+                info!("Imported message from {}, inserted: {}, updated: {}, unchanged: {}",
+                    peer, import_count.inserted, import_count.updated, import_count.unchanged);
+                for problem in problems {
+                    warn!("Parse problem in message from {}: {}", peer, problem);
+                }
+            },
+            Ok(FileResult::Duplicate(sha256)) => info!("Message from {} already imported, sha256: {}", peer, sha256),
+            Ok(FileResult::Skipped(reason)) => warn!("Skipped message from {}: {}", peer, reason),
+            Ok(FileResult::Failed(reason)) => {
+                error!("Import failed for message from {}: {}", peer, reason);
+
+                if let Err(e) = db_storage.schema_version() {
+                    error!("Database connection lost: {}", e);
+                    storage = None;
+                }
+            },
+            Err(e) => error!("Invalid message from {}: {}", peer, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a recorded message to a DirectIP server, returns true if the server confirmed it.
+pub fn send_message(address: &str, raw: &[u8]) -> Result<bool> {
+    let mut stream = TcpStream::connect(address).chain_err(|| format!("Could not connect to: '{}'", address))?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.write_all(raw)?;
+
+    let response = read_message(&mut stream)?;
+
+    match information_element(&response[3..]) {
+        IResult::Done(_, (IEI_MO_CONFIRMATION, content)) if content.len() == 1 => Ok(content[0] == 1),
+        _ => bail!("Invalid confirmation from server: {:?}", response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::net::{TcpListener};
+    use std::thread;
+
+    use chrono::{NaiveDateTime};
+
+    use batch::{FileResult};
+    use test_data::{date_time};
+    use database::{Table, Storage};
+    use migration::{migrate};
+    use sqlite_storage::{SqliteStorage};
+    use station::{StationRegistry};
+
+    use super::{decode_message, handle_connection, send_message};
+
+    // Header: CDR 1234, IMEI 300025060007390, status 0, MOMSN 2, MTMSN 0, session time 2016-09-19 00:05:19
+    // Location: south, west, 29° 45.553', 71° 9.770', CEP radius 4
+    // Payload: one simple record
+    const MESSAGE: [u8; 65] = [
+        1, 0, 62,
+        0x01, 0, 28, 0, 0, 4, 210, 51, 48, 48, 48, 50, 53, 48, 54, 48, 48, 48, 55, 51, 57, 48, 0, 0, 2, 0, 0, 87, 223, 43, 191,
+        0x03, 0, 11, 3, 29, 177, 241, 71, 38, 42, 0, 0, 0, 4,
+        0x02, 0, 14, 0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 96, 0, 0, 0,
+    ];
+
+    const STATIONS: &str = r#"
+        [[station]]
+        imei = "300025060007390"
+        name = "Santa_Gracia"
+        latitude = -29.7574
+        longitude = -71.1612
+        elevation = 707.0
+        layout = "simple_and_multiple"
+    "#;
+
+    #[test]
+    fn test_decode_message() {
+        let message = decode_message(&MESSAGE).unwrap();
+
+        assert_eq!(message.cdr_reference, 1234);
+        assert_eq!(message.file_name(), "300025060007390_000002.sbd");
+        assert_eq!(message.sbd_message.session_time, Some(NaiveDateTime::parse_from_str("2016-09-19 00:05:19", "%Y-%m-%d %H:%M:%S").unwrap()));
+        assert_eq!(message.sbd_message.session_status, Some("00 - Transfer OK".to_string()));
+        assert_eq!(message.sbd_message.latitude, Some(-(29.0 + 45553.0 / 60000.0)));
+        assert_eq!(message.sbd_message.longitude, Some(-(71.0 + 9770.0 / 60000.0)));
+        assert_eq!(message.sbd_message.cep_radius, Some(4.0));
+        assert_eq!(message.payload.len(), 14);
+
+        assert!(decode_message(&MESSAGE[..60]).is_err());
+    }
+
+    #[test]
+    fn test_replay_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let client = thread::spawn(move || send_message(&address, &MESSAGE).unwrap());
+
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station_registry = StationRegistry::from_toml(STATIONS).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let result = handle_connection(&mut stream, &mut storage, &station_registry, None).unwrap();
+
+        assert!(client.join().unwrap());
+        assert!(matches!(result, FileResult::Imported(..)));
+
+        assert_eq!(storage.get_id(Table::BatteryData, "Santa_Gracia", date_time()).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station_registry = StationRegistry::from_toml(&STATIONS.replace("300025060007390", "300025060000500")).unwrap();
+
+        // A message of an unknown station is confirmed once it is archived
+        let client_address = address.clone();
+        let client = thread::spawn(move || send_message(&client_address, &MESSAGE).unwrap());
+        let (mut stream, _) = listener.accept().unwrap();
+        let result = handle_connection(&mut stream, &mut storage, &station_registry, None).unwrap();
+
+        assert!(client.join().unwrap());
+        assert!(matches!(result, FileResult::Skipped(_)));
+        assert_eq!(storage.raw_messages(true, None).unwrap().len(), 1);
+
+        // A message that can not be recorded is not confirmed
+        let record_dir = env::temp_dir().join(format!("sbd_db_import_record_dir_{}", ::std::process::id()));
+        File::create(&record_dir).unwrap();
+
+        let client = thread::spawn(move || send_message(&address, &MESSAGE).unwrap());
+        let (mut stream, _) = listener.accept().unwrap();
+        let result = handle_connection(&mut stream, &mut storage, &station_registry, Some(&record_dir));
+        fs::remove_file(&record_dir).unwrap();
+
+        assert!(!client.join().unwrap());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,186 @@
+// External modules:
+use chrono::{NaiveDateTime};
+
+// System modules:
+use std::fmt;
+
+// Internal modules:
+use error::{Result};
+use data_parser::{WeatherStationData};
+use database::{Table, ImportCount, Storage};
+use station::{Station};
+
+/// A column of an existing record that would get a new value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnChange {
+    pub column: String,
+    pub old_value: Option<f64>,
+    pub new_value: f64,
+}
+
+/// What an import would do with one record.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordChange {
+    Insert(Vec<(String, f64)>),
+    Update(Vec<ColumnChange>),
+    Unchanged,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordDiff {
+    pub table: Table,
+    pub station_name: String,
+    pub date_time: NaiveDateTime,
+    pub change: RecordChange,
+}
+
+/// NaN is stored as NULL (SQLite, MySQL) or compared equal to NaN (PostgreSQL), so both mean no value.
+fn stored_value(value: Option<f64>) -> Option<f64> {
+    value.filter(|value| !value.is_nan())
+}
+
+/// Compare the values of a record with the values stored in the database.
+fn diff_record(storage: &mut dyn Storage, table: Table, station_name: &str, date_time: NaiveDateTime,
+        values: Vec<(String, f64)>) -> Result<RecordDiff> {
+    let columns: Vec<&str> = values.iter().map(|(column, _)| column.as_str()).collect();
+
+    let change = match storage.get_values(table, &columns, station_name, date_time)? {
+        None => RecordChange::Insert(values),
+        Some(stored_values) => {
+            let changes: Vec<ColumnChange> = values.iter().zip(stored_values)
+                .filter(|((_, new_value), old_value)| stored_value(*old_value) != stored_value(Some(*new_value)))
+                .map(|((column, new_value), old_value)| ColumnChange { column: column.clone(), old_value, new_value: *new_value })
+                .collect();
+
+            if changes.is_empty() {
+                RecordChange::Unchanged
+            } else {
+                RecordChange::Update(changes)
+            }
+        }
+    };
+
+    Ok(RecordDiff {
+        table,
+        station_name: station_name.to_string(),
+        date_time,
+        change,
+    })
+}
+
+/// Work out what import_to_db() would write for the data, only reads from the database.
+pub fn dry_run(storage: &mut dyn Storage, station: &Station, data: &WeatherStationData) -> Result<Vec<RecordDiff>> {
+    let columns = data.definition.columns();
+
+    data.records.iter()
+        .map(|record| {
+            let values = columns.iter().zip(&record.values).map(|(column, value)| (column.to_string(), *value)).collect();
+            diff_record(storage, data.definition.table, &station.name, record.date_time, values)
+        })
+        .collect()
+}
+
+/// Count the records like import_to_db() does.
+pub fn count_diffs(diffs: &[RecordDiff]) -> ImportCount {
+    let mut import_count = ImportCount::default();
+
+    for diff in diffs {
+        match diff.change {
+            RecordChange::Insert(_) => import_count.inserted += 1,
+            RecordChange::Update(_) => import_count.updated += 1,
+            RecordChange::Unchanged => import_count.unchanged += 1,
+        }
+    }
+
+    import_count
+}
+
+fn format_value(value: Option<f64>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "NULL".to_string(),
+    }
+}
+
+impl fmt::Display for RecordDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = format!("{} {} {}", self.table.name(), self.station_name, self.date_time);
+
+        match self.change {
+            RecordChange::Insert(ref values) => {
+                writeln!(f, "+ {} (insert)", header)?;
+                for (column, value) in values {
+                    writeln!(f, "    + {}: {}", column, value)?;
+                }
+            },
+            RecordChange::Update(ref changes) => {
+                writeln!(f, "~ {} (update)", header)?;
+                for change in changes {
+                    writeln!(f, "    - {}: {}", change.column, format_value(change.old_value))?;
+                    writeln!(f, "    + {}: {}", change.column, change.new_value)?;
+                }
+            },
+            RecordChange::Unchanged => {
+                writeln!(f, "= {} (unchanged)", header)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_parser::{parse_data};
+    use test_data::{MULTIPLE, received_at, station};
+    use database::{ImportCount, import_to_db};
+    use migration::{migrate};
+    use sqlite_storage::{SqliteStorage};
+
+    use super::{RecordChange, dry_run, count_diffs};
+
+    #[test]
+    fn test_dry_run() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station = station("Santa_Gracia");
+
+        let data = parse_data(&MULTIPLE, &station.layout, received_at()).unwrap();
+        let diffs = dry_run(&mut storage, &station, &data).unwrap();
+        assert_eq!(count_diffs(&diffs), ImportCount { inserted: 1, updated: 0, unchanged: 0 });
+
+        // Nothing was written
+        let diffs = dry_run(&mut storage, &station, &data).unwrap();
+        assert_eq!(count_diffs(&diffs), ImportCount { inserted: 1, updated: 0, unchanged: 0 });
+
+        import_to_db(&mut storage, &station, data).unwrap();
+        let data = parse_data(&MULTIPLE, &station.layout, received_at()).unwrap();
+        let diffs = dry_run(&mut storage, &station, &data).unwrap();
+        assert_eq!(diffs[0].change, RecordChange::Unchanged);
+
+        let mut changed = MULTIPLE.to_vec();
+        changed[9] = 223;
+        let data = parse_data(&changed, &station.layout, received_at()).unwrap();
+        let diffs = dry_run(&mut storage, &station, &data).unwrap();
+        match diffs[0].change {
+            RecordChange::Update(ref changes) => {
+                assert_eq!(changes.len(), 1);
+                assert_eq!(changes[0].column, "air_temperature");
+                assert_eq!(changes[0].old_value, Some(15.02));
+                assert!(changes[0].new_value != 15.02);
+            },
+            ref change => panic!("expected update, got: {:?}", change)
+        }
+        assert!(diffs[0].to_string().starts_with("~ multiple_data Santa_Gracia 2016-09-19 00:00:00 (update)\n    - air_temperature: 15.02\n"));
+
+        // FP2 NaN in the air temperature is stored as NULL
+        changed[8] = 0x9f;
+        changed[9] = 0xfe;
+        let data = parse_data(&changed, &station.layout, received_at()).unwrap();
+        assert!(data.records[0].values[0].is_nan());
+        import_to_db(&mut storage, &station, data).unwrap();
+        let data = parse_data(&changed, &station.layout, received_at()).unwrap();
+        let diffs = dry_run(&mut storage, &station, &data).unwrap();
+        assert_eq!(diffs[0].change, RecordChange::Unchanged);
+    }
+}
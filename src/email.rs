@@ -0,0 +1,397 @@
+// External modules:
+use chrono::{NaiveDateTime};
+use mailparse::{parse_mail, ParsedMail, MailHeaderMap};
+use regex::Regex;
+
+// System modules:
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock};
+
+// Internal modules:
+use error::{Result, ResultExt};
+use database::{Storage};
+use batch::{BatchSummary, FileResult, import_data};
+use station::{StationRegistry, parse_sbd_file_name};
+
+/// An SBD file attached to an Iridium e-mail.
+/// The subject is "SBD Msg From Unit: <IMEI>", the attachment is named <IMEI>_<MOMSN>.sbd.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbdAttachment {
+    pub imei: String,
+    pub momsn: u32,
+    pub file_name: String,
+    pub binary_data: Vec<u8>,
+    pub message: SbdMessage,
+}
+
+/// The metadata of an SBD message from the body of the Iridium e-mail:
+///
+/// MOMSN: 2143
+/// MTMSN: 0
+/// Time of Session (UTC): Mon Sep 19 00:05:19 2016
+/// Session Status: 00 - Transfer OK
+/// Message Size (bytes): 28
+///
+/// Unit Location: Lat = -29.75922 Long = -71.16284
+/// CEPradius = 4
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbdMessage {
+    pub imei: String,
+    pub momsn: u32,
+    pub mtmsn: Option<u32>,
+    pub session_time: Option<NaiveDateTime>,
+    pub session_status: Option<String>,
+    pub message_size: Option<u32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Radius of the circular error probable of the location in km
+    pub cep_radius: Option<f64>,
+}
+
+/// One e-mail and where it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub source: String,
+    pub raw: Vec<u8>,
+}
+
+/// The patterns of the subject and the metadata lines of the body, compiled once for all e-mails.
+struct EmailPatterns {
+    subject_imei: Regex,
+    momsn: Regex,
+    mtmsn: Regex,
+    session_time: Regex,
+    session_status: Regex,
+    message_size: Regex,
+    latitude: Regex,
+    longitude: Regex,
+    cep_radius: Regex,
+}
+
+fn patterns() -> &'static EmailPatterns {
+    static PATTERNS: OnceLock<EmailPatterns> = OnceLock::new();
+
+    PATTERNS.get_or_init(|| EmailPatterns {
+        subject_imei: Regex::new(r"SBD Msg From Unit: (\d{15})").unwrap(),
+        momsn: Regex::new(r"(?m)^MOMSN: (\d+)").unwrap(),
+        mtmsn: Regex::new(r"(?m)^MTMSN: (\d+)").unwrap(),
+        session_time: Regex::new(r"(?m)^Time of Session \(UTC\): (.+)$").unwrap(),
+        session_status: Regex::new(r"(?m)^Session Status: (.+)$").unwrap(),
+        message_size: Regex::new(r"(?m)^Message Size \(bytes\): (\d+)").unwrap(),
+        latitude: Regex::new(r"Lat = (-?[\d.]+)").unwrap(),
+        longitude: Regex::new(r"Long = (-?[\d.]+)").unwrap(),
+        cep_radius: Regex::new(r"CEPradius = ([\d.]+)").unwrap(),
+    })
+}
+
+fn subject_imei(mail: &ParsedMail) -> Option<String> {
+    let subject = mail.headers.get_first_value("Subject")?;
+    patterns().subject_imei.captures(&subject).map(|captures| captures[1].to_string())
+}
+
+fn body_text(mail: &ParsedMail) -> String {
+    mail.parts()
+        .filter(|part| part.ctype.mimetype == "text/plain")
+        .filter_map(|part| part.get_body().ok())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn body_value(body: &str, re: &Regex) -> Option<String> {
+    re.captures(body).map(|captures| captures[1].trim().to_string())
+}
+
+/// Extract the metadata of the SBD message from the body of the e-mail.
+/// Lines that are missing or can not be parsed are left empty.
+pub fn parse_sbd_message(imei: &str, momsn: u32, body: &str) -> SbdMessage {
+    let patterns = patterns();
+
+    SbdMessage {
+        imei: imei.to_string(),
+        momsn,
+        mtmsn: body_value(body, &patterns.mtmsn).and_then(|value| value.parse().ok()),
+        session_time: body_value(body, &patterns.session_time)
+            .and_then(|value| NaiveDateTime::parse_from_str(&value, "%a %b %e %H:%M:%S %Y").ok()),
+        session_status: body_value(body, &patterns.session_status),
+        message_size: body_value(body, &patterns.message_size).and_then(|value| value.parse().ok()),
+        latitude: body_value(body, &patterns.latitude).and_then(|value| value.parse().ok()),
+        longitude: body_value(body, &patterns.longitude).and_then(|value| value.parse().ok()),
+        cep_radius: body_value(body, &patterns.cep_radius).and_then(|value| value.parse().ok()),
+    }
+}
+
+fn attachment_name(part: &ParsedMail) -> Option<String> {
+    part.get_content_disposition().params.get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned()
+}
+
+/// Find all .sbd attachments of an Iridium e-mail and decode them.
+/// IMEI and MOMSN are taken from the file name of the attachment, if that is not in the
+/// Iridium format from the subject and the MOMSN line of the body.
+pub fn sbd_attachments(raw: &[u8]) -> Result<Vec<SbdAttachment>> {
+    let mail = parse_mail(raw).chain_err(|| "Could not parse e-mail")?;
+    let body = body_text(&mail);
+    let mut attachments = Vec::new();
+
+    for part in mail.parts() {
+        let name = match attachment_name(part) {
+            Some(ref name) if name.to_lowercase().ends_with(".sbd") => name.clone(),
+            _ => continue,
+        };
+
+        let binary_data = part.get_body_raw().chain_err(|| format!("Could not decode attachment: '{}'", name))?;
+
+        let (imei, momsn) = match parse_sbd_file_name(&name) {
+            Some(sbd_file_name) => (sbd_file_name.imei, sbd_file_name.momsn),
+            None => match (subject_imei(&mail), body_value(&body, &patterns().momsn).and_then(|value| value.parse().ok())) {
+                (Some(imei), Some(momsn)) => (imei, momsn),
+                _ => bail!("Could not determine IMEI and MOMSN of attachment: '{}'", name)
+            }
+        };
+
+        // The station registry finds the station by this name
+        let file_name = format!("{}_{:06}.sbd", imei, momsn);
+
+        let message = parse_sbd_message(&imei, momsn, &body);
+
+        attachments.push(SbdAttachment {
+            imei,
+            momsn,
+            file_name,
+            binary_data,
+            message,
+        });
+    }
+
+    Ok(attachments)
+}
+
+/// Split an mbox archive into its messages, each message starts with a "From " line.
+pub fn split_mbox(content: &[u8]) -> Vec<&[u8]> {
+    let mut starts = Vec::new();
+
+    for (i, _) in content.iter().enumerate() {
+        if (i == 0 || content[i - 1] == b'\n') && content[i..].starts_with(b"From ") {
+            starts.push(i);
+        }
+    }
+
+    starts.iter().enumerate().map(|(n, start)| {
+        let end = starts.get(n + 1).cloned().unwrap_or(content.len());
+        // Skip the "From " separator line itself
+        let message = &content[*start..end];
+        match message.iter().position(|byte| *byte == b'\n') {
+            Some(line_end) => &message[line_end + 1..],
+            None => &message[message.len()..],
+        }
+    }).collect()
+}
+
+fn is_maildir(path: &Path) -> bool {
+    path.join("cur").is_dir() && path.join("new").is_dir()
+}
+
+/// Read all e-mails from a .eml file, an mbox archive or a Maildir folder (new/ and cur/).
+pub fn read_messages(input: &str) -> Result<Vec<EmailMessage>> {
+    let path = Path::new(input);
+    let mut messages = Vec::new();
+
+    if path.is_dir() {
+        if !is_maildir(path) {
+            bail!("Directory is not a Maildir folder, it has no cur/ and new/: '{}'", input);
+        }
+
+        let mut files: Vec<PathBuf> = Vec::new();
+        for sub_dir in &["new", "cur"] {
+            for entry in fs::read_dir(path.join(sub_dir)).chain_err(|| format!("Could not read Maildir: '{}'", input))? {
+                let entry_path = entry?.path();
+                if entry_path.is_file() {
+                    files.push(entry_path);
+                }
+            }
+        }
+        files.sort();
+
+        for file in files {
+            messages.push(EmailMessage {
+                source: file.to_string_lossy().into_owned(),
+                raw: fs::read(&file).chain_err(|| format!("Could not read e-mail: '{}'", file.display()))?,
+            });
+        }
+    } else {
+        let content = fs::read(path).chain_err(|| format!("Could not read e-mail file: '{}'", input))?;
+
+        if content.starts_with(b"From ") {
+            for (n, message) in split_mbox(&content).into_iter().enumerate() {
+                messages.push(EmailMessage {
+                    source: format!("{}#{}", input, n + 1),
+                    raw: message.to_vec(),
+                });
+            }
+        } else {
+            messages.push(EmailMessage {
+                source: input.to_string(),
+                raw: content,
+            });
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Import the SBD attachments of all e-mails, in MOMSN order per IMEI, one transaction per attachment.
+pub fn import_emails(storage: &mut dyn Storage, station_registry: &StationRegistry, inputs: &[&str],
+        station_override: Option<&str>, dry_run_only: bool) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+    let mut attachments = Vec::new();
+
+    for input in inputs {
+        // An input that can not be read fails on its own, like a file of import_files()
+        let messages = match read_messages(input) {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("Could not read e-mails from: '{}': {}", input, e);
+                summary.add(PathBuf::from(input), FileResult::Failed(e.to_string()));
+                continue;
+            }
+        };
+
+        for message in messages {
+            match sbd_attachments(&message.raw) {
+                Ok(ref found) if found.is_empty() => {
+                    summary.add(PathBuf::from(&message.source), FileResult::Skipped("No .sbd attachment".to_string()));
+                },
+                Ok(found) => {
+                    attachments.extend(found.into_iter().map(|attachment| (message.source.clone(), attachment)));
+                },
+                Err(e) => {
+                    summary.add(PathBuf::from(&message.source), FileResult::Failed(e.to_string()));
+                }
+            }
+        }
+    }
+
+    attachments.sort_by(|(_, a), (_, b)| (&a.imei, a.momsn).cmp(&(&b.imei, b.momsn)));
+
+    for (source, attachment) in attachments {
+        let label = PathBuf::from(format!("{}: {}", source, attachment.file_name));
+        info!("Importing attachment: '{}'", label.display());
+
+        let result = import_data(storage, station_registry, &attachment.file_name, attachment.binary_data,
+            Some(&attachment.message), station_override, dry_run_only);
+
+        match result {
+            FileResult::Imported(_, ref problems) => for problem in problems {
+                warn!("Parse problem in attachment: '{}': {}", label.display(), problem);
+            },
+            FileResult::Duplicate(ref sha256) => info!("Attachment already imported: '{}', sha256: {}", label.display(), sha256),
+            FileResult::Skipped(ref reason) => warn!("Skipped attachment: '{}': {}", label.display(), reason),
+            FileResult::Failed(ref reason) => error!("Import failed for attachment: '{}': {}", label.display(), reason),
+        }
+
+        summary.add(label, result);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use chrono::{NaiveDateTime};
+
+    use test_data::{SIMPLE, station};
+    use layout::{builtin_layouts};
+    use migration::{migrate};
+    use sqlite_storage::{SqliteStorage};
+    use station::{StationRegistry};
+
+    use super::{sbd_attachments, split_mbox, import_emails};
+
+    const IRIDIUM_EMAIL: &str = "From: sbdservice@sbd.iridium.com
+Subject: SBD Msg From Unit: 300025060007390
+MIME-Version: 1.0
+Content-Type: multipart/mixed; boundary=\"SBD.Boundary.605592468\"
+
+--SBD.Boundary.605592468
+Content-Type: text/plain; charset=\"us-ascii\"
+Content-Transfer-Encoding: 7bit
+
+MOMSN: 2
+MTMSN: 0
+Time of Session (UTC): Mon Sep 19 00:05:19 2016
+Session Status: 00 - Transfer OK
+Message Size (bytes): 14
+
+Unit Location: Lat = -29.75922 Long = -71.16284
+CEPradius = 4
+
+--SBD.Boundary.605592468
+Content-Type: application/x-zip-compressed; name=\"300025060007390_000002.sbd\"
+Content-Disposition: attachment; filename=\"300025060007390_000002.sbd\"
+Content-Transfer-Encoding: base64
+
+AI1AMgAAAABE/GAAAAA=
+
+--SBD.Boundary.605592468--
+";
+
+    #[test]
+    fn test_sbd_attachments() {
+        let attachments = sbd_attachments(IRIDIUM_EMAIL.as_bytes()).unwrap();
+
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].imei, "300025060007390");
+        assert_eq!(attachments[0].momsn, 2);
+        assert_eq!(attachments[0].file_name, "300025060007390_000002.sbd");
+        assert_eq!(attachments[0].binary_data, SIMPLE.to_vec());
+
+        let message = &attachments[0].message;
+        assert_eq!(message.mtmsn, Some(0));
+        assert_eq!(message.session_time, Some(NaiveDateTime::parse_from_str("2016-09-19 00:05:19", "%Y-%m-%d %H:%M:%S").unwrap()));
+        assert_eq!(message.session_status, Some("00 - Transfer OK".to_string()));
+        assert_eq!(message.message_size, Some(14));
+        assert_eq!(message.latitude, Some(-29.75922));
+        assert_eq!(message.longitude, Some(-71.16284));
+        assert_eq!(message.cep_radius, Some(4.0));
+
+        // IMEI from the subject and MOMSN from the body if the attachment has another name
+        let renamed = IRIDIUM_EMAIL.replace("300025060007390_000002.sbd", "data.sbd");
+        let attachments = sbd_attachments(renamed.as_bytes()).unwrap();
+        assert_eq!(attachments[0].file_name, "300025060007390_000002.sbd");
+    }
+
+    #[test]
+    fn test_split_mbox() {
+        let mbox = format!("From sbdservice@sbd.iridium.com Mon Sep 19 00:05:20 2016\n{}\nFrom sbdservice@sbd.iridium.com Mon Sep 19 01:05:20 2016\n{}",
+            IRIDIUM_EMAIL, IRIDIUM_EMAIL);
+        let messages = split_mbox(mbox.as_bytes());
+
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with(b"From: sbdservice"));
+        assert_eq!(sbd_attachments(messages[1]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unreadable_input_fails_alone() {
+        let path = env::temp_dir().join(format!("sbd_db_import_email_{}.eml", ::std::process::id()));
+        fs::write(&path, IRIDIUM_EMAIL).unwrap();
+        let missing = env::temp_dir().join(format!("sbd_db_import_missing_{}.mbox", ::std::process::id()));
+
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station_registry = StationRegistry::new(vec![station("Santa_Gracia")], builtin_layouts()).unwrap();
+
+        let inputs = [missing.to_str().unwrap(), path.to_str().unwrap()];
+        let summary = import_emails(&mut storage, &station_registry, &inputs, None, false).unwrap();
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, missing);
+        assert_eq!(summary.imported.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,9 +1,41 @@
+// error_chain 0.11 implements Error::description() and cause(), which are deprecated
+#![allow(deprecated)]
+
 use mysql;
 use std;
+use toml;
+use rusqlite;
+use postgres;
+use native_tls;
+use serde_json;
 
 error_chain!{
     foreign_links {
         MySQLError(mysql::Error);
         IOError(std::io::Error);
+        TomlError(toml::de::Error);
+        SqliteError(rusqlite::Error);
+        PostgresError(postgres::Error);
+        TlsError(native_tls::Error);
+        JsonError(serde_json::Error);
+    }
+
+    errors {
+        TrailingBytes(offset: usize, count: usize) {
+            description("trailing bytes after the last record")
+            display("{} trailing bytes after the last record at offset {}", count, offset)
+        }
+        PartialRecord(offset: usize, count: usize, record_size: usize) {
+            description("incomplete record")
+            display("incomplete record at offset {}: {} of {} bytes", offset, count, record_size)
+        }
+        CorruptedRecord(offset: usize, count: usize) {
+            description("corrupted record")
+            display("{} bytes without a plausible record skipped at offset {}", count, offset)
+        }
+        MissingValue(offset: usize, field: String) {
+            description("NaN in a mandatory field")
+            display("NaN in mandatory field '{}' at offset {}", field, offset)
+        }
     }
 }
@@ -0,0 +1,158 @@
+// External modules:
+use nom::{IResult};
+use byteorder::{BigEndian, ByteOrder};
+use chrono::{NaiveDateTime};
+
+// System modules:
+use std::fmt::Write;
+
+// Internal modules:
+use data_parser::{parse_data, parse_date_time, split_fp2, u16_to_f64, split_fp4, fp4_to_f64};
+use layout::{FieldDefinition, FieldType, RecordDefinition, RecordLayout, Strictness};
+
+/// Campbell Scientific defines 7999 as the largest allowed FP2 mantissa.
+const FP2_MAX_MANTISSA: u16 = 7999;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<String>>().join(" ")
+}
+
+fn bits(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:08b}", byte)).collect::<Vec<String>>().join(" ")
+}
+
+fn inspect_date_time(report: &mut String, binary_data: &[u8], offset: usize) {
+    let bytes = &binary_data[offset..offset + 8];
+
+    let value = match parse_date_time(bytes) {
+        IResult::Done(_, date_time) => date_time.to_string(),
+        _ => "invalid".to_string(),
+    };
+
+    let _ = writeln!(report, "{:>6}  {:<17}  {:<35}  {:<25}  {:<26}  {}", offset, hex(&bytes[0..4]), bits(&bytes[0..4]), "date_time", "seconds, little endian", value);
+    let _ = writeln!(report, "{:>6}  {:<17}  {:<35}  unused", offset + 4, hex(&bytes[4..8]), bits(&bytes[4..8]));
+}
+
+fn inspect_fp2(report: &mut String, binary_data: &[u8], offset: usize, field: &str) {
+    let bytes = &binary_data[offset..offset + 2];
+    let data = BigEndian::read_u16(bytes);
+    let parts = split_fp2(data);
+
+    let split = format!("{} exp={} mant={}{}", if parts.negative { "-" } else { "+" }, parts.exponent, parts.mantissa,
+        if parts.mantissa > FP2_MAX_MANTISSA { " (> 7999)" } else { "" });
+
+    let _ = writeln!(report, "{:>6}  {:<17}  {:<35}  {:<25}  {:<26}  {}", offset, hex(bytes), bits(bytes), field, split, u16_to_f64(data));
+}
+
+fn inspect_fp4(report: &mut String, binary_data: &[u8], offset: usize, field: &str) {
+    let bytes = &binary_data[offset..offset + 4];
+    let data = BigEndian::read_u32(bytes);
+    let parts = split_fp4(data);
+
+    let split = format!("{} exp={} mant={}", if parts.negative { "-" } else { "+" }, parts.exponent, parts.mantissa);
+
+    let _ = writeln!(report, "{:>6}  {:<17}  {:<35}  {:<25}  {:<26}  {}", offset, hex(bytes), bits(bytes), field, split, fp4_to_f64(data));
+}
+
+fn inspect_field(report: &mut String, binary_data: &[u8], offset: usize, field: &FieldDefinition) {
+    match field.field_type {
+        FieldType::Fp2 => inspect_fp2(report, binary_data, offset, &field.name),
+        FieldType::Fp4 => inspect_fp4(report, binary_data, offset, &field.name),
+        field_type => {
+            let bytes = &binary_data[offset..offset + field_type.size()];
+            let _ = writeln!(report, "{:>6}  {:<17}  {:<35}  {:<25}  {:<26}  {}", offset, hex(bytes), bits(bytes), field.name,
+                field_type.name(), field_type.decode(bytes));
+        }
+    }
+}
+
+fn inspect_record(report: &mut String, binary_data: &[u8], offset: usize, definition: &RecordDefinition) {
+    inspect_date_time(report, binary_data, offset);
+
+    let mut field_offset = offset + 8;
+
+    for field in &definition.fields {
+        inspect_field(report, binary_data, field_offset, field);
+        field_offset += field.field_type.size();
+    }
+}
+
+/// Show every field of the SBD data with its byte offset, raw bytes, the FP2 bit fields
+/// (sign, decimal position, mantissa) and the decoded value, followed by the bytes the parser did not use.
+/// received_at: when the payload was received, see parse_data().
+pub fn inspect(binary_data: &[u8], layout: &RecordLayout, received_at: NaiveDateTime) -> String {
+    let mut report = String::new();
+
+    let _ = writeln!(report, "Size: {} bytes, layout: {}", binary_data.len(), layout.name);
+
+    // The trailing bytes are shown below, so they are not an error here
+    let mut layout = layout.clone();
+    layout.strictness = Strictness::Lenient;
+
+    let used = match parse_data(binary_data, &layout, received_at) {
+        Ok(data) => {
+            let record_size = data.definition.record_size();
+            let header_size = data.definition.header.len();
+
+            let _ = writeln!(report, "Record definition: {}, chosen by rule: {}", data.definition.name, data.detection);
+
+            if header_size > 0 {
+                let _ = writeln!(report, "\nHeader, offset 0\n{:>6}  {}", 0, hex(&binary_data[0..header_size]));
+            }
+
+            let mut used = header_size;
+
+            for (i, record) in data.records.iter().enumerate() {
+                if record.offset > used {
+                    let _ = writeln!(report, "\nSkipped bytes without a plausible timestamp: {}, offset {}", record.offset - used, used);
+                    hex_dump(&mut report, binary_data, used, record.offset);
+                }
+
+                let _ = writeln!(report, "\nRecord {} ({}), offset {}", i + 1, data.definition.name, record.offset);
+                let _ = writeln!(report, "{:>6}  {:<17}  {:<35}  {:<25}  {:<26}  value", "offset", "bytes", "bits", "field", "sign exponent mantissa");
+                inspect_record(&mut report, binary_data, record.offset, &data.definition);
+                used = record.offset + record_size;
+            }
+
+            used
+        },
+        Err(e) => {
+            let _ = writeln!(report, "Could not be parsed: {}", e);
+            0
+        }
+    };
+
+    if used < binary_data.len() {
+        let _ = writeln!(report, "\nTrailing bytes not used by the parser: {}, offset {}", binary_data.len() - used, used);
+        hex_dump(&mut report, binary_data, used, binary_data.len());
+    }
+
+    report
+}
+
+/// The bytes from start to end, 16 per line.
+fn hex_dump(report: &mut String, binary_data: &[u8], start: usize, end: usize) {
+    for (i, chunk) in binary_data[start..end].chunks(16).enumerate() {
+        let _ = writeln!(report, "{:>6}  {}", start + i * 16, hex(chunk));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use layout::{builtin_layout};
+    use test_data::{received_at};
+
+    use super::{inspect};
+
+    #[test]
+    fn test_inspect() {
+        let binary_data = [0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 96, 0, 0, 0, 171, 205];
+        let report = inspect(&binary_data, &builtin_layout("simple").unwrap(), received_at());
+
+        assert!(report.contains("Record definition: simple, chosen by rule: single record definition"));
+        assert!(report.contains("Record 1 (simple), offset 0"));
+        assert!(report.contains("     0  00 8d 40 32        00000000 10001101 01000000 00110010  date_time                  seconds, little endian      2016-09-19 00:00:00"));
+        assert!(report.contains("     8  44 fc              01000100 11111100                    solar_battery_voltage      + exp=2 mant=1276           12.76"));
+        assert!(report.contains("Trailing bytes not used by the parser: 2, offset 14\n    14  ab cd\n"));
+    }
+}
@@ -0,0 +1,366 @@
+// External modules:
+use byteorder::{BigEndian, ByteOrder};
+use regex::Regex;
+
+// System modules:
+use std::collections::HashSet;
+use std::str::FromStr;
+
+// Internal modules:
+use error::{Error, Result};
+use data_parser::{u16_to_f64, fp4_to_f64};
+use database::{Table};
+
+// Record layouts describe the binary payload of a datalogger program, so a changed program
+// only needs a new [[layout]] in the config file instead of a new release.
+//
+// Each record starts with the 8 byte timestamp, followed by the fields in the given order.
+// The layouts "simple", "multiple" and "simple_and_multiple" are built in, see builtin_layouts().
+
+/// Campbell Scientific final storage data types.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum FieldType {
+    /// 2 byte Campbell floating point, see u16_to_f64()
+    #[serde(rename = "FP2")]
+    Fp2,
+    /// 4 byte Campbell floating point, see fp4_to_f64()
+    #[serde(rename = "FP4")]
+    Fp4,
+    /// 4 byte IEEE 754 single precision floating point
+    #[serde(rename = "IEEE4", alias = "IEEE4B")]
+    Ieee4,
+    /// 2 byte unsigned integer
+    #[serde(rename = "UINT2")]
+    Uint2,
+    /// 4 byte unsigned integer
+    #[serde(rename = "UINT4")]
+    Uint4,
+    /// 1 byte boolean, 0: false (0.0), everything else: true (1.0)
+    #[serde(rename = "BOOL")]
+    Bool,
+    /// 2 byte boolean
+    #[serde(rename = "BOOL2")]
+    Bool2,
+    /// 4 byte boolean
+    #[serde(rename = "BOOL4")]
+    Bool4,
+}
+
+impl FieldType {
+    /// The name used in the config file.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FieldType::Fp2 => "FP2",
+            FieldType::Fp4 => "FP4",
+            FieldType::Ieee4 => "IEEE4",
+            FieldType::Uint2 => "UINT2",
+            FieldType::Uint4 => "UINT4",
+            FieldType::Bool => "BOOL",
+            FieldType::Bool2 => "BOOL2",
+            FieldType::Bool4 => "BOOL4",
+        }
+    }
+
+    /// Number of bytes of a value.
+    pub fn size(&self) -> usize {
+        match *self {
+            FieldType::Bool => 1,
+            FieldType::Fp2 | FieldType::Uint2 | FieldType::Bool2 => 2,
+            FieldType::Fp4 | FieldType::Ieee4 | FieldType::Uint4 | FieldType::Bool4 => 4,
+        }
+    }
+
+    /// Decode one value, bytes must have the size of the type.
+    /// All multi byte types are big endian, like the final storage of the datalogger.
+    pub fn decode(&self, bytes: &[u8]) -> f64 {
+        match *self {
+            FieldType::Fp2 => u16_to_f64(BigEndian::read_u16(bytes)),
+            FieldType::Fp4 => fp4_to_f64(BigEndian::read_u32(bytes)),
+            FieldType::Ieee4 => BigEndian::read_f32(bytes) as f64,
+            FieldType::Uint2 => BigEndian::read_u16(bytes) as f64,
+            FieldType::Uint4 => BigEndian::read_u32(bytes) as f64,
+            FieldType::Bool | FieldType::Bool2 | FieldType::Bool4 => {
+                if bytes.iter().any(|byte| *byte != 0) { 1.0 } else { 0.0 }
+            }
+        }
+    }
+}
+
+/// One value of a record and the column it is written to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FieldDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    pub column: String,
+    /// A NaN in this field is a parse problem, see Strictness
+    #[serde(default)]
+    pub mandatory: bool,
+}
+
+/// How the parser handles trailing bytes, incomplete records and NaN in mandatory fields.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strictness {
+    /// The payload is rejected with an error that gives the offset
+    Strict,
+    /// The problem is logged as warning and the records are imported
+    #[default]
+    Warn,
+    /// The problem is logged as info and the records are imported
+    Lenient,
+}
+
+impl FromStr for Strictness {
+    type Err = Error;
+
+    fn from_str(strictness: &str) -> Result<Strictness> {
+        match strictness {
+            "strict" => Ok(Strictness::Strict),
+            "warn" => Ok(Strictness::Warn),
+            "lenient" => Ok(Strictness::Lenient),
+            _ => bail!("Unknown strictness: '{}', expected strict, warn or lenient", strictness)
+        }
+    }
+}
+
+/// The fields of one kind of record and the table the records are written to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RecordDefinition {
+    pub name: String,
+    pub table: Table,
+    /// false: the payload is exactly one record, true: one or more records
+    #[serde(default)]
+    pub repeated: bool,
+    /// Bytes the datalogger program sends in front of the records to identify them, empty if there are none
+    #[serde(default)]
+    pub header: Vec<u8>,
+    pub fields: Vec<FieldDefinition>,
+}
+
+impl RecordDefinition {
+    /// Size of one record in bytes, including the timestamp.
+    pub fn record_size(&self) -> usize {
+        8 + self.fields.iter().map(|field| field.field_type.size()).sum::<usize>()
+    }
+
+    pub fn columns(&self) -> Vec<&str> {
+        self.fields.iter().map(|field| field.column.as_str()).collect()
+    }
+}
+
+/// The kinds of records a station sends, a payload is decoded with the first record definition that fits.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RecordLayout {
+    pub name: String,
+    #[serde(default)]
+    pub strictness: Strictness,
+    /// Skip corrupted repeated records and continue at the next plausible timestamp
+    #[serde(default)]
+    pub recovery: bool,
+    #[serde(rename = "record")]
+    pub records: Vec<RecordDefinition>,
+}
+
+/// The columns every table has, they can not be the target of a field.
+const RESERVED_COLUMNS: [&str; 4] = ["id", "timestamp", "station", "sbd_message_id"];
+
+impl RecordLayout {
+    /// Check names and columns, they are used in SQL statements and must be plain identifiers.
+    /// The columns must exist in the table of the record, see Table::columns().
+    pub fn validate(&self) -> Result<()> {
+        let identifier_re = Regex::new(r"^[a-z][a-z0-9_]{0,63}$").unwrap();
+
+        if self.records.is_empty() {
+            bail!("Layout '{}' has no record definitions", self.name);
+        }
+
+        let mut headers = HashSet::new();
+
+        for record in &self.records {
+            if !record.header.is_empty() && !headers.insert(&record.header) {
+                bail!("Duplicate header {:?} in record '{}' of layout '{}'", record.header, record.name, self.name);
+            }
+
+            if record.fields.is_empty() {
+                bail!("Record '{}' of layout '{}' has no fields", record.name, self.name);
+            }
+
+            let mut columns = HashSet::new();
+
+            for field in &record.fields {
+                if !identifier_re.is_match(&field.name) || !identifier_re.is_match(&field.column) {
+                    bail!("Invalid field '{}' or column '{}' in record '{}' of layout '{}', expected lower case letters, digits and _",
+                        field.name, field.column, record.name, self.name);
+                }
+                if RESERVED_COLUMNS.contains(&field.column.as_str()) {
+                    bail!("Column '{}' in record '{}' of layout '{}' is reserved", field.column, record.name, self.name);
+                }
+                if !record.table.columns().contains(&field.column.as_str()) {
+                    bail!("Unknown column '{}' in record '{}' of layout '{}', the columns of table {} are: {}",
+                        field.column, record.name, self.name, record.table.name(), record.table.columns().join(", "));
+                }
+                if !columns.insert(&field.column) {
+                    bail!("Duplicate column '{}' in record '{}' of layout '{}'", field.column, record.name, self.name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn fp2_fields(fields: &[(&str, &str)]) -> Vec<FieldDefinition> {
+    fields.iter().map(|(name, column)| FieldDefinition {
+        name: name.to_string(),
+        field_type: FieldType::Fp2,
+        column: column.to_string(),
+        mandatory: false,
+    }).collect()
+}
+
+/// Battery data, one record of 14 bytes.
+fn simple_record() -> RecordDefinition {
+    RecordDefinition {
+        name: "simple".to_string(),
+        table: Table::BatteryData,
+        repeated: false,
+        header: Vec::new(),
+        fields: fp2_fields(&[
+            ("solar_battery_voltage", "battery_voltage"),
+            ("lithium_battery_voltage", "li_battery_voltage"),
+            ("wind_direction", "wind_dir"),
+        ]),
+    }
+}
+
+/// Weather data, one or more records of 28 bytes.
+fn multiple_record() -> RecordDefinition {
+    RecordDefinition {
+        name: "multiple".to_string(),
+        table: Table::MultipleData,
+        repeated: true,
+        header: Vec::new(),
+        fields: fp2_fields(&[
+            ("air_temperature", "air_temperature"),
+            ("air_relative_humidity", "air_relative_humidity"),
+            ("solar_radiation", "solar_radiation"),
+            ("soil_water_content", "soil_water_content"),
+            ("soil_temperature", "soil_temperature"),
+            ("wind_speed", "wind_speed"),
+            ("wind_max", "wind_max"),
+            ("wind_direction", "wind_direction"),
+            ("precipitation", "precipitation"),
+            ("air_pressure", "air_pressure"),
+        ]),
+    }
+}
+
+/// The layouts of the original datalogger program.
+pub fn builtin_layouts() -> Vec<RecordLayout> {
+    vec![
+        RecordLayout {
+            name: "simple".to_string(),
+            strictness: Strictness::default(),
+            recovery: false,
+            records: vec![simple_record()],
+        },
+        RecordLayout {
+            name: "multiple".to_string(),
+            strictness: Strictness::default(),
+            recovery: false,
+            records: vec![multiple_record()],
+        },
+        RecordLayout {
+            name: "simple_and_multiple".to_string(),
+            strictness: Strictness::default(),
+            recovery: false,
+            records: vec![multiple_record(), simple_record()],
+        },
+    ]
+}
+
+/// A built in layout by name.
+pub fn builtin_layout(name: &str) -> Result<RecordLayout> {
+    match builtin_layouts().into_iter().find(|layout| layout.name == name) {
+        Some(layout) => Ok(layout),
+        None => bail!("Unknown record layout: '{}', expected simple, multiple, simple_and_multiple or a layout from the config file", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use database::{Table};
+
+    use super::{FieldType, RecordLayout, builtin_layout, builtin_layouts};
+
+    #[test]
+    fn test_layout_from_toml() {
+        let layout: RecordLayout = toml::from_str(r#"
+            name = "cr1000x_hourly"
+
+            [[record]]
+            name = "hourly"
+            table = "multiple_data"
+            repeated = true
+            fields = [
+                { name = "air_temperature", type = "FP2", column = "air_temperature" },
+                { name = "wind_gust", type = "FP2", column = "wind_max" },
+            ]
+        "#).unwrap();
+
+        layout.validate().unwrap();
+        assert_eq!(layout.records[0].table, Table::MultipleData);
+        assert_eq!(layout.records[0].fields[1].field_type, FieldType::Fp2);
+        assert_eq!(layout.records[0].columns(), vec!["air_temperature", "wind_max"]);
+        assert_eq!(layout.records[0].record_size(), 12);
+
+        let mut invalid = layout.clone();
+        invalid.records[0].fields[0].column = "station".to_string();
+        assert!(invalid.validate().is_err());
+
+        invalid.records[0].fields[0].column = "air_temperature; DROP TABLE multiple_data".to_string();
+        assert!(invalid.validate().is_err());
+
+        invalid.records[0].fields[0].column = "battery_voltage".to_string();
+        assert!(invalid.validate().is_err());
+
+        invalid.records[0].table = Table::BatteryData;
+        invalid.records[0].fields[1].column = "wind_dir".to_string();
+        invalid.validate().unwrap();
+    }
+
+    #[test]
+    fn test_decode_field_types() {
+        assert_eq!(FieldType::Fp2.decode(&[0x44, 0xfc]), 12.76);
+        assert_eq!(FieldType::Fp4.decode(&[0xc2, 0xa0, 0x00, 0x00]), -2.5);
+        assert_eq!(FieldType::Ieee4.decode(&[0x3f, 0x80, 0x00, 0x00]), 1.0);
+        assert_eq!(FieldType::Ieee4.decode(&[0xc0, 0x49, 0x0f, 0xdb]), -3.1415927410125732);
+        assert!(FieldType::Ieee4.decode(&[0x7f, 0xc0, 0x00, 0x00]).is_nan());
+        assert_eq!(FieldType::Uint2.decode(&[0xff, 0xfe]), 65534.0);
+        assert_eq!(FieldType::Uint4.decode(&[0x00, 0x01, 0x00, 0x02]), 65538.0);
+        assert_eq!(FieldType::Bool.decode(&[0x00]), 0.0);
+        assert_eq!(FieldType::Bool.decode(&[0xff]), 1.0);
+        assert_eq!(FieldType::Bool4.decode(&[0xff, 0xff, 0xff, 0xff]), 1.0);
+
+        let field_types: Vec<FieldType> = toml::from_str::<toml::Value>(r#"types = ["FP2", "FP4", "IEEE4", "IEEE4B", "UINT2", "UINT4", "BOOL", "BOOL2", "BOOL4"]"#)
+            .unwrap()["types"].clone().try_into().unwrap();
+        let sizes: Vec<usize> = field_types.iter().map(|field_type| field_type.size()).collect();
+        assert_eq!(sizes, vec![2, 4, 4, 4, 2, 4, 1, 2, 4]);
+        assert_eq!(field_types[3], FieldType::Ieee4);
+    }
+
+    #[test]
+    fn test_builtin_layouts() {
+        assert_eq!(builtin_layout("simple").unwrap().records[0].record_size(), 14);
+        assert_eq!(builtin_layout("multiple").unwrap().records[0].record_size(), 28);
+        assert_eq!(builtin_layout("simple_and_multiple").unwrap().records.len(), 2);
+        assert!(builtin_layout("other").is_err());
+
+        for layout in builtin_layouts() {
+            layout.validate().unwrap();
+        }
+    }
+}
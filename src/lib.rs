@@ -0,0 +1,47 @@
+// External crates:
+#[macro_use] extern crate log;
+#[macro_use] extern crate error_chain;
+#[macro_use] extern crate nom;
+#[macro_use] extern crate combine;
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate mysql;
+#[macro_use(named_params)] extern crate rusqlite;
+
+extern crate time;
+extern crate regex;
+extern crate chrono;
+extern crate byteorder;
+extern crate serde;
+extern crate toml;
+extern crate postgres;
+extern crate postgres_native_tls;
+extern crate native_tls;
+extern crate serde_json;
+extern crate glob;
+extern crate inotify;
+extern crate mailparse;
+extern crate sha2;
+
+// Internal modules:
+pub mod error;
+pub mod data_parser;
+pub mod layout;
+pub mod database;
+pub mod migration;
+pub mod dry_run;
+pub mod output;
+pub mod inspect;
+pub mod batch;
+pub mod watch;
+pub mod email;
+pub mod directip;
+pub mod reprocess;
+pub mod mysql_storage;
+pub mod sqlite_storage;
+pub mod postgres_storage;
+pub mod station;
+pub mod db_config;
+pub mod password;
+
+#[cfg(test)]
+mod test_data;
@@ -1,25 +1,15 @@
 // External crates:
 #[macro_use] extern crate log;
 #[macro_use] extern crate error_chain;
-#[macro_use] extern crate nom;
-#[macro_use] extern crate combine;
 
 extern crate simplelog;
-extern crate time;
-extern crate regex;
 extern crate chrono;
-extern crate byteorder;
 extern crate clap;
-extern crate mysql;
-
-// Internal modules:
-mod error;
-mod data_parser;
-mod database;
+extern crate sbd_station_db_import;
 
 // External modules:
-use clap::{App, Arg};
-use chrono::Local;
+use clap::{App, AppSettings, Arg, SubCommand};
+use chrono::{Local, Utc};
 use simplelog::{Config, TermLogger, WriteLogger, LogLevelFilter};
 use log::LogLevel;
 
@@ -27,52 +17,350 @@ use log::LogLevel;
 use std::fs::OpenOptions;
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 // Internal modules:
-use error::{Result, ResultExt};
-use data_parser::{parse_data};
-use database::{import_to_db};
+use sbd_station_db_import::error::{Result, ResultExt};
+use sbd_station_db_import::data_parser::{parse_data};
+use sbd_station_db_import::layout::{RecordLayout, Strictness, builtin_layout};
+use sbd_station_db_import::database::{open_storage};
+use sbd_station_db_import::batch::{collect_files, import_files};
+use sbd_station_db_import::watch::{WatchDirs, watch};
+use sbd_station_db_import::email::{import_emails};
+use sbd_station_db_import::directip::{directip_server, send_message};
+use sbd_station_db_import::reprocess::{reprocess};
+use sbd_station_db_import::migration::{init_db, migrate, check_schema_version};
+use sbd_station_db_import::station::{StationRegistry};
+use sbd_station_db_import::db_config::{DbConfig};
+use sbd_station_db_import::password::{read_password};
+use sbd_station_db_import::output::{format_data};
+use sbd_station_db_import::inspect::{inspect};
+
+fn read_sbd_file(file_name: &str) -> Result<Vec<u8>> {
+    let mut input_file = File::open(file_name).chain_err(|| format!("Could not open sbd file: '{}'", file_name))?;
+    let mut binary_data = Vec::new();
+    let data_size = input_file.read_to_end(&mut binary_data)?;
+
+    info!("Bytes read: {}", data_size);
 
-quick_main!(|| -> Result<()> {
+    Ok(binary_data)
+}
+
+/// Load the station registry, the strictness and recovery given on the command line override the ones of the layouts.
+fn load_registry(config_file: &str, strictness: Option<Strictness>, recovery: bool) -> Result<StationRegistry> {
+    let mut registry = StationRegistry::load(config_file)?;
+
+    if let Some(strictness) = strictness {
+        registry.set_strictness(strictness);
+    }
+
+    if recovery {
+        registry.set_recovery();
+    }
+
+    Ok(registry)
+}
+
+/// The layout given on the command line, otherwise the layout of the station, parse and inspect also work without a station.
+/// A layout given by name is looked up in the config file, the built in layouts are available without one.
+fn record_layout(layout: Option<&str>, config_file: &str, file_name: &str, station_override: Option<&str>,
+        strictness: Option<Strictness>, recovery: bool) -> Result<RecordLayout> {
+    let registry = StationRegistry::load(config_file);
+
+    let mut layout = if let Some(layout) = layout {
+        match registry {
+            Ok(registry) => registry.layout(layout)?.clone(),
+            Err(e) => {
+                info!("Config file not loaded ({}), only the built in layouts are available", e);
+                builtin_layout(layout)?
+            }
+        }
+    } else {
+        let station_layout = registry
+            .and_then(|registry| registry.resolve(file_name, station_override).map(|station| station.layout.clone()));
+
+        match station_layout {
+            Ok(layout) => layout,
+            Err(e) => {
+                info!("Station not found ({}), using layout simple_and_multiple", e);
+                builtin_layout("simple_and_multiple")?
+            }
+        }
+    };
+
+    if let Some(strictness) = strictness {
+        layout.strictness = strictness;
+    }
+
+    if recovery {
+        layout.recovery = true;
+    }
+
+    Ok(layout)
+}
+
+quick_main!(|| -> Result<i32> {
 
     let matches = App::new("sbd_db_import")
         .version("0.2")
         .author("Willi Kappler")
         .about("Import binary SBD files from weatherstations into the database, files sent via e-mail")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("db_user")
             .long("db_user")
             .help("Username for the database")
             .takes_value(true)
-            .required(true)
         )
         .arg(
             Arg::with_name("db_password")
             .long("db_password")
-            .help("Password for the database")
+            .help("Password for the database, discouraged since it is visible in ps and shell history, use --db_password_file, SBD_DB_PASSWORD or ~/.my.cnf instead")
             .takes_value(true)
-            .required(true)
+        )
+        .arg(
+            Arg::with_name("db_password_file")
+            .long("db_password_file")
+            .help("File containing the password for the database, must not be readable by group or others")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_option_file")
+            .long("db_option_file")
+            .help("MySQL option file with the password in the [client] section, defaults to ~/.my.cnf")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_backend")
+            .long("db_backend")
+            .help("The database backend: mysql (default), postgres or sqlite, for sqlite --db_name is the database file")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_host")
+            .long("db_host")
+            .help("Hostname of the database server")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_port")
+            .long("db_port")
+            .help("TCP port of the database server (default: 3306 for mysql, 5432 for postgres)")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_name")
+            .long("db_name")
+            .help("Name of the database")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_socket")
+            .long("db_socket")
+            .help("Unix socket of the database server, used instead of host and port (for postgres the directory containing the socket)")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_tls")
+            .long("db_tls")
+            .help("Use a TLS connection to the database server")
+        )
+        .arg(
+            Arg::with_name("db_tls_ca")
+            .long("db_tls_ca")
+            .help("CA certificate to verify the database server")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("db_tls_skip_domain_validation")
+            .long("db_tls_skip_domain_validation")
+            .help("Do not check that the certificate matches the hostname of the database server")
+        )
+        .arg(
+            Arg::with_name("db_batch_size")
+            .long("db_batch_size")
+            .help("Number of records written with one statement, 1 writes each record on its own, at most 5461 (default: 100)")
+            .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("config")
+            .long("config")
+            .help("The config file with the database settings and the station registry")
+            .takes_value(true)
+            .default_value("sbd_db_import.toml")
         )
         .arg(
             Arg::with_name("station")
             .long("station")
-            .help("The name of the weatherstation")
+            .help("The name of the weatherstation, overrides the station detected from the IMEI in the file name")
             .takes_value(true)
-            .required(true)
+        )
+        .arg(
+            Arg::with_name("strictness")
+            .long("strictness")
+            .help("How trailing bytes, incomplete records and NaN in mandatory fields are handled, overrides the strictness of the layouts: strict rejects the file, warn (default) and lenient import the records")
+            .takes_value(true)
+            .possible_values(&["strict", "warn", "lenient"])
+        )
+        .arg(
+            Arg::with_name("recovery")
+            .long("recovery")
+            .help("Skip corrupted records and continue at the next plausible timestamp, the skipped bytes are handled like an incomplete record")
         )
         .arg(
             Arg::with_name("file_name")
             .long("file_name")
-            .help("The binary SBD file")
+            .help("The binary SBD files, directories containing .sbd files or glob patterns, imported in MOMSN order")
             .takes_value(true)
+            .multiple(true)
             .required(true)
         )
+        .arg(
+            Arg::with_name("dry_run")
+            .long("dry_run")
+            .alias("dry-run")
+            .help("Parse the file and show what would be inserted or updated, nothing is written to the database")
+        )
+        .subcommand(
+            SubCommand::with_name("init-db")
+            .about("Create the tables in an empty database")
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+            .about("Update the tables of an existing database to the current schema version")
+        )
+        .subcommand(
+            SubCommand::with_name("email")
+            .about("Import the .sbd attachments of Iridium e-mails")
+            .arg(
+                Arg::with_name("input")
+                .long("input")
+                .help("E-mail files (.eml), mbox archives or Maildir folders")
+                .takes_value(true)
+                .multiple(true)
+                .required(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+            .about("Watch an inbox directory and import new .sbd files as they appear")
+            .arg(
+                Arg::with_name("inbox")
+                .long("inbox")
+                .help("The directory the SBD files are written to")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("processed_dir")
+                .long("processed_dir")
+                .help("Imported files are moved to <processed_dir>/<station>/ (default: <inbox>/processed)")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("failed_dir")
+                .long("failed_dir")
+                .help("Files that could not be imported are moved here with a .error file (default: <inbox>/failed)")
+                .takes_value(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("reprocess")
+            .about("Parse the payloads archived in raw_messages again with the current parser, use --station to select one station")
+            .arg(
+                Arg::with_name("failed_only")
+                .long("failed_only")
+                .help("Only the payloads that could not be parsed or were skipped before")
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("directip-server")
+            .about("Receive MO messages pushed by the Iridium gateway over DirectIP and import their payload")
+            .arg(
+                Arg::with_name("listen")
+                .long("listen")
+                .help("The address and TCP port to listen on")
+                .takes_value(true)
+                .default_value("0.0.0.0:10800")
+            )
+            .arg(
+                Arg::with_name("record_dir")
+                .long("record_dir")
+                .help("Save every received message in this directory, so it can be sent again with directip-replay")
+                .takes_value(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("directip-replay")
+            .about("Send recorded DirectIP messages to a directip-server, like the Iridium gateway does")
+            .arg(
+                Arg::with_name("address")
+                .long("address")
+                .help("The address and TCP port of the server")
+                .takes_value(true)
+                .default_value("127.0.0.1:10800")
+            )
+            .arg(
+                Arg::with_name("file_name")
+                .long("file_name")
+                .help("The recorded messages, sent in the given order")
+                .takes_value(true)
+                .multiple(true)
+                .required(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+            .about("Show the byte offset, raw bytes, FP2 bit fields and decoded value of every field in a binary SBD file")
+            .arg(
+                Arg::with_name("file_name")
+                .long("file_name")
+                .help("The binary SBD file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("layout")
+                .long("layout")
+                .help("The record layout: simple, multiple, simple_and_multiple or a layout from the config file, by default the layout of the station detected from the file name, otherwise simple_and_multiple")
+                .takes_value(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("parse")
+            .about("Decode a binary SBD file and print its records, no database is needed")
+            .arg(
+                Arg::with_name("file_name")
+                .long("file_name")
+                .help("The binary SBD file")
+                .takes_value(true)
+                .required(true)
+            )
+            .arg(
+                Arg::with_name("format")
+                .long("format")
+                .help("The output format")
+                .takes_value(true)
+                .possible_values(&["json", "csv", "table"])
+                .default_value("table")
+            )
+            .arg(
+                Arg::with_name("layout")
+                .long("layout")
+                .help("The record layout: simple, multiple, simple_and_multiple or a layout from the config file, by default the layout of the station detected from the file name, otherwise simple_and_multiple")
+                .takes_value(true)
+            )
+        )
         .get_matches();
 
-    let db_user = matches.value_of("db_user").unwrap();
-    let db_password = matches.value_of("db_password").unwrap();
-    let station_name = matches.value_of("station").unwrap();
-    let file_name = matches.value_of("file_name").unwrap();
+    let cli_password = matches.value_of("db_password");
+    let config_file = matches.value_of("config").unwrap();
+    let station_override = matches.value_of("station");
+    let strictness = match matches.value_of("strictness") {
+        Some(strictness) => Some(strictness.parse()?),
+        None => None,
+    };
+    let recovery = matches.is_present("recovery");
 
     // Initialize logger
     let dt = Local::now();
@@ -94,29 +382,154 @@ quick_main!(|| -> Result<()> {
         warn!("Could not open log fle: '{}', using sdtout instead!", &log_filename);
     }
 
-    if file_name.starts_with("300025060000500") {
-        info!("Pan_de_Azucar");
-    } else if file_name.starts_with("300025060004660") {
-        info!("La_Campana");
-    } else if file_name.starts_with("300025060007390") {
-        info!("Santa_Gracia");
-    } else if file_name.starts_with("300025060008580") {
-        info!("Nahuelbuta");
+    if let Some(parse_matches) = matches.subcommand_matches("parse") {
+        let file_name = parse_matches.value_of("file_name").unwrap();
+        let format = parse_matches.value_of("format").unwrap().parse()?;
+        let layout = record_layout(parse_matches.value_of("layout"), config_file, file_name, station_override, strictness, recovery)?;
+
+        let weatherstation_data = parse_data(&read_sbd_file(file_name)?, &layout, Utc::now().naive_utc())?;
+        print!("{}", format_data(&weatherstation_data, format)?);
+
+        return Ok(0);
     }
 
-    let mut input_file = File::open(file_name).chain_err(|| format!("Could not open sbd file: '{}'", file_name))?;
-    let mut binary_data = Vec::new();
-    let data_size = input_file.read_to_end(&mut binary_data)?;
+    if let Some(inspect_matches) = matches.subcommand_matches("inspect") {
+        let file_name = inspect_matches.value_of("file_name").unwrap();
+        let layout = record_layout(inspect_matches.value_of("layout"), config_file, file_name, station_override, strictness, recovery)?;
 
-    info!("Bytes read: {}", data_size);
+        print!("{}", inspect(&read_sbd_file(file_name)?, &layout, Utc::now().naive_utc()));
 
-    let weatherstation_data = parse_data(binary_data)?;
+        return Ok(0);
+    }
+
+    if let Some(replay_matches) = matches.subcommand_matches("directip-replay") {
+        let address = replay_matches.value_of("address").unwrap();
+        let mut rejected = 0;
+        let mut sent = 0;
+
+        for file_name in replay_matches.values_of("file_name").unwrap() {
+            let confirmed = send_message(address, &read_sbd_file(file_name)?)?;
+            println!("{}: {}", file_name, if confirmed { "confirmed" } else { "rejected" });
+
+            sent += 1;
+            if !confirmed {
+                rejected += 1;
+            }
+        }
+
+        println!("messages sent: {}, rejected: {}", sent, rejected);
+
+        return Ok(if rejected == 0 { 0 } else if rejected == sent { 1 } else { 2 });
+    }
 
-    info!("data: {:?}", weatherstation_data);
+    let mut db_config = DbConfig::load(config_file)?;
+    db_config.override_from_env()?;
+    db_config.override_from(|key| {
+        let arg_name = format!("db_{}", key);
+        match matches.value_of(&arg_name) {
+            Some(value) => Some(value.to_string()),
+            None if matches.is_present(&arg_name) => Some("true".to_string()),
+            None => None
+        }
+    })?;
+    db_config.validate()?;
+
+    // SQLite has no password, a (missing) password source must not stop the import
+    let db_password = if db_config.backend == "sqlite" {
+        None
+    } else {
+        read_password(&db_config, cli_password)?
+    };
+
+    if matches.subcommand_matches("init-db").is_some() {
+        let mut storage = open_storage(&db_config, db_password.as_deref(), false)?;
+        init_db(&mut *storage)?;
+        info!("database initialized");
+        return Ok(0);
+    }
+
+    if matches.subcommand_matches("migrate").is_some() {
+        let mut storage = open_storage(&db_config, db_password.as_deref(), false)?;
+        migrate(&mut *storage)?;
+        info!("database migrated");
+        return Ok(0);
+    }
+
+    if let Some(watch_matches) = matches.subcommand_matches("watch") {
+        let inbox = PathBuf::from(watch_matches.value_of("inbox").unwrap());
+        let dirs = WatchDirs {
+            processed: watch_matches.value_of("processed_dir").map_or_else(|| inbox.join("processed"), PathBuf::from),
+            failed: watch_matches.value_of("failed_dir").map_or_else(|| inbox.join("failed"), PathBuf::from),
+            inbox,
+        };
+
+        let station_registry = load_registry(config_file, strictness, recovery)?;
+        watch(&dirs, &station_registry, || open_storage(&db_config, db_password.as_deref(), false))?;
+        return Ok(0);
+    }
+
+    if let Some(server_matches) = matches.subcommand_matches("directip-server") {
+        let address = server_matches.value_of("listen").unwrap();
+        let record_dir = server_matches.value_of("record_dir").map(Path::new);
+
+        let station_registry = load_registry(config_file, strictness, recovery)?;
+        directip_server(address, &station_registry, record_dir, || open_storage(&db_config, db_password.as_deref(), false))?;
+        return Ok(0);
+    }
+
+    let station_registry = load_registry(config_file, strictness, recovery)?;
+    let dry_run_only = matches.is_present("dry_run");
+
+    let summary = if let Some(reprocess_matches) = matches.subcommand_matches("reprocess") {
+        if dry_run_only {
+            bail!("--dry_run is not supported by reprocess");
+        }
+
+        let mut storage = open_storage(&db_config, db_password.as_deref(), false)?;
+        check_schema_version(&mut *storage)?;
+
+        reprocess(&mut *storage, &station_registry, station_override, reprocess_matches.is_present("failed_only"))?
+    } else if let Some(email_matches) = matches.subcommand_matches("email") {
+        let inputs: Vec<&str> = email_matches.values_of("input").unwrap().collect();
+
+        let mut storage = open_storage(&db_config, db_password.as_deref(), dry_run_only)?;
+        check_schema_version(&mut *storage)?;
+
+        import_emails(&mut *storage, &station_registry, &inputs, station_override, dry_run_only)?
+    } else {
+        let inputs: Vec<&str> = matches.values_of("file_name").unwrap().collect();
+        let files = collect_files(&inputs)?;
+
+        if files.is_empty() {
+            bail!("No SBD files found in: {}", inputs.join(", "));
+        }
+
+        let mut storage = open_storage(&db_config, db_password.as_deref(), dry_run_only)?;
+        check_schema_version(&mut *storage)?;
+
+        import_files(&mut *storage, &station_registry, files, station_override, dry_run_only)
+    };
+
+    for (file, reason) in &summary.skipped {
+        println!("skipped: {}: {}", file.display(), reason);
+    }
+
+    for (file, reason) in &summary.failed {
+        println!("failed: {}: {}", file.display(), reason);
+    }
+
+    for (file, problem) in &summary.problems {
+        println!("problem: {}: {}", file.display(), problem);
+    }
 
-    import_to_db(db_user, db_password, station_name, weatherstation_data)?;
+    println!("{}files imported: {}, parse problems: {}, already imported: {}, skipped: {}, failed: {}, rows inserted: {}, updated: {}, unchanged: {}",
+        if dry_run_only { "dry run, nothing written, " } else { "" },
+        summary.imported.len(), summary.problems.len(), summary.duplicates.len(), summary.skipped.len(), summary.failed.len(),
+        summary.import_count.inserted, summary.import_count.updated, summary.import_count.unchanged);
 
-    info!("import successfull to database");
+    info!("import finished, files imported: {}, skipped: {}, failed: {}, rows inserted: {}, updated: {}",
+        summary.imported.len(), summary.skipped.len(), summary.failed.len(),
+        summary.import_count.inserted, summary.import_count.updated);
 
-    Ok(())
-});
+    Ok(summary.exit_code())
+});
\ No newline at end of file
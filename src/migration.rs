@@ -0,0 +1,112 @@
+// Internal modules:
+use error::{Result};
+use database::{Storage};
+
+/// A schema change, the SQL for each backend is in its storage module (apply_migration).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+}
+
+/// All migrations in the order they are applied, new migrations are added at the end.
+pub const MIGRATIONS: [Migration; 5] = [
+    Migration {
+        version: 1,
+        description: "Create tables battery_data and multiple_data",
+    },
+    Migration {
+        version: 2,
+        description: "Add unique key on (station, timestamp) to battery_data and multiple_data",
+    },
+    Migration {
+        version: 3,
+        description: "Create table sbd_messages and link battery_data and multiple_data to it",
+    },
+    Migration {
+        version: 4,
+        description: "Create table raw_messages for the original SBD payloads",
+    },
+    Migration {
+        version: 5,
+        description: "Allow raw_messages without a station for skipped payloads",
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS[MIGRATIONS.len() - 1].version
+}
+
+/// Apply all migrations that are newer than the current schema version of the database.
+/// Returns the number of migrations applied.
+pub fn migrate(storage: &mut dyn Storage) -> Result<usize> {
+    let current_version = storage.schema_version()?;
+    let mut applied = 0;
+
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > current_version) {
+        info!("Applying migration {}: {}", migration.version, migration.description);
+        storage.apply_migration(migration)?;
+        applied += 1;
+    }
+
+    info!("Database schema version: {}, migrations applied: {}", latest_version(), applied);
+
+    Ok(applied)
+}
+
+/// Create all tables in an empty database.
+pub fn init_db(storage: &mut dyn Storage) -> Result<()> {
+    let current_version = storage.schema_version()?;
+
+    if current_version > 0 {
+        bail!("Database is already initialized with schema version {}, use migrate to update it", current_version);
+    }
+
+    migrate(storage)?;
+
+    Ok(())
+}
+
+/// Make sure the database has the schema this version of the importer writes to.
+pub fn check_schema_version(storage: &mut dyn Storage) -> Result<()> {
+    let current_version = storage.schema_version()?;
+
+    if current_version < latest_version() {
+        bail!("Database schema version {} is older than {}, run init-db for a new database or migrate for an existing one",
+            current_version, latest_version());
+    } else if current_version > latest_version() {
+        bail!("Database schema version {} is newer than {}, update the importer", current_version, latest_version());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use database::{Storage};
+    use sqlite_storage::{SqliteStorage};
+
+    use super::{MIGRATIONS, latest_version, migrate, init_db, check_schema_version};
+
+    #[test]
+    fn test_migrations_are_numbered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+
+        assert_eq!(storage.schema_version().unwrap(), 0);
+        assert!(check_schema_version(&mut storage).is_err());
+
+        init_db(&mut storage).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), latest_version());
+        check_schema_version(&mut storage).unwrap();
+
+        assert!(init_db(&mut storage).is_err());
+        assert_eq!(migrate(&mut storage).unwrap(), 0);
+    }
+}
@@ -0,0 +1,612 @@
+// External modules:
+use chrono::{NaiveDateTime};
+use regex::Regex;
+use mysql::{OptsBuilder, Params, Pool, PooledConn, Row, SslOpts, Transaction, TxOpts, Value};
+use mysql::prelude::{Queryable};
+
+// System modules:
+use std::path::{PathBuf};
+use std::sync::{OnceLock};
+
+// Internal modules:
+use error::{Result, ResultExt};
+use data_parser::{Record};
+use db_config::{DbConfig};
+use database::{Table, ImportCount, ParseStatus, RawMessage, StoredRawMessage, Storage, StorageTransaction};
+use migration::{Migration};
+use email::{SbdMessage};
+
+const CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version INT UNSIGNED NOT NULL PRIMARY KEY,
+    description VARCHAR(255) NOT NULL,
+    applied_at DATETIME NOT NULL
+)";
+
+const CREATE_BATTERY_DATA: &str = "CREATE TABLE IF NOT EXISTS battery_data (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME,
+    station VARCHAR(32),
+    battery_voltage DOUBLE,
+    li_battery_voltage DOUBLE,
+    wind_dir DOUBLE,
+    KEY timestamp (timestamp)
+)";
+
+const CREATE_MULTIPLE_DATA: &str = "CREATE TABLE IF NOT EXISTS multiple_data (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME,
+    station VARCHAR(32),
+    air_temperature DOUBLE,
+    air_relative_humidity DOUBLE,
+    solar_radiation DOUBLE,
+    soil_water_content DOUBLE,
+    soil_temperature DOUBLE,
+    wind_speed DOUBLE,
+    wind_max DOUBLE,
+    wind_direction DOUBLE,
+    precipitation DOUBLE,
+    air_pressure DOUBLE,
+    KEY timestamp (timestamp)
+)";
+
+const CREATE_SBD_MESSAGES: &str = "CREATE TABLE IF NOT EXISTS sbd_messages (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    imei VARCHAR(15) NOT NULL,
+    momsn INT UNSIGNED NOT NULL,
+    mtmsn INT UNSIGNED,
+    station VARCHAR(32),
+    session_time DATETIME,
+    session_status VARCHAR(64),
+    message_size INT UNSIGNED,
+    latitude DOUBLE,
+    longitude DOUBLE,
+    cep_radius DOUBLE,
+    UNIQUE KEY imei_momsn (imei, momsn)
+)";
+
+// LAST_INSERT_ID(id) makes the id of an existing message available as last insert id.
+
+const UPSERT_SBD_MESSAGE: &str = "INSERT INTO sbd_messages (
+    imei,
+    momsn,
+    mtmsn,
+    station,
+    session_time,
+    session_status,
+    message_size,
+    latitude,
+    longitude,
+    cep_radius
+) VALUES (
+    :imei,
+    :momsn,
+    :mtmsn,
+    :station,
+    :session_time,
+    :session_status,
+    :message_size,
+    :latitude,
+    :longitude,
+    :cep_radius
+) ON DUPLICATE KEY UPDATE
+    id = LAST_INSERT_ID(id),
+    mtmsn = VALUES(mtmsn),
+    station = VALUES(station),
+    session_time = VALUES(session_time),
+    session_status = VALUES(session_status),
+    message_size = VALUES(message_size),
+    latitude = VALUES(latitude),
+    longitude = VALUES(longitude),
+    cep_radius = VALUES(cep_radius)";
+
+const CREATE_RAW_MESSAGES: &str = "CREATE TABLE IF NOT EXISTS raw_messages (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    sha256 CHAR(64) NOT NULL,
+    imei VARCHAR(15),
+    momsn INT UNSIGNED,
+    station VARCHAR(32) NOT NULL,
+    file_name VARCHAR(255) NOT NULL,
+    received_at DATETIME NOT NULL,
+    payload BLOB NOT NULL,
+    parse_status VARCHAR(16) NOT NULL,
+    parse_error TEXT,
+    parsed_at DATETIME NOT NULL,
+    sbd_message_id INT UNSIGNED,
+    UNIQUE KEY sha256 (sha256),
+    KEY sbd_message_id (sbd_message_id)
+)";
+
+const ALLOW_RAW_MESSAGES_WITHOUT_STATION: &str = "ALTER TABLE raw_messages MODIFY station VARCHAR(32) NULL";
+
+// The payload, IMEI, MOMSN, file name and receive time of the first import are kept,
+// the station only if it was known.
+
+const UPSERT_RAW_MESSAGE: &str = "INSERT INTO raw_messages (
+    sha256,
+    imei,
+    momsn,
+    station,
+    file_name,
+    received_at,
+    payload,
+    parse_status,
+    parse_error,
+    parsed_at,
+    sbd_message_id
+) VALUES (
+    :sha256,
+    :imei,
+    :momsn,
+    :station,
+    :file_name,
+    :received_at,
+    :payload,
+    :parse_status,
+    :parse_error,
+    UTC_TIMESTAMP(),
+    :sbd_message_id
+) ON DUPLICATE KEY UPDATE
+    station = COALESCE(station, VALUES(station)),
+    parse_status = VALUES(parse_status),
+    parse_error = VALUES(parse_error),
+    parsed_at = VALUES(parsed_at),
+    sbd_message_id = COALESCE(VALUES(sbd_message_id), sbd_message_id)";
+
+// Both tables have a unique key on (station, timestamp), added by migration 2, see add_unique_key().
+// A record that is sent again overwrites the existing values.
+
+/// The parameters of one record: timestamp, station and the values, see upsert_columns().
+fn record_params(station_name: &str, record: &Record) -> Vec<Value> {
+    let mut params = vec![Value::from(record.date_time), Value::from(station_name)];
+    params.extend(record.values.iter().map(|value| Value::from(*value)));
+    params
+}
+
+/// All columns written by an upsert: timestamp, station and the value columns.
+fn upsert_columns<'b>(columns: &[&'b str]) -> Vec<&'b str> {
+    let mut upsert_columns = vec!["timestamp", "station"];
+    upsert_columns.extend_from_slice(columns);
+    upsert_columns
+}
+
+/// Count the result of an upsert by its affected rows:
+/// 1: new row inserted, 2: existing row updated, 0: existing row with the same values
+fn count_upsert(import_count: &mut ImportCount, affected_rows: u64) {
+    match affected_rows {
+        1 => import_count.inserted += 1,
+        2 => import_count.updated += 1,
+        _ => import_count.unchanged += 1,
+    }
+}
+
+/// Count the result of a multi-row upsert.
+/// The server reports the number of rows that already existed in the info string:
+/// "Records: 100  Duplicates: 3  Warnings: 0", affected rows are 1 per insert and 2 per update.
+/// Duplicates counts the existing rows whether their values changed or not, an unchanged row has
+/// 0 affected rows because the connection does not set CLIENT_FOUND_ROWS, see benches/batch_insert.rs.
+fn count_batch_upsert(import_count: &mut ImportCount, rows: u64, affected_rows: u64, info: &str) -> Result<()> {
+    // No info string for a single row
+    if rows == 1 {
+        count_upsert(import_count, affected_rows);
+        return Ok(())
+    }
+
+    static DUPLICATES_RE: OnceLock<Regex> = OnceLock::new();
+    let re = DUPLICATES_RE.get_or_init(|| Regex::new(r"Duplicates: (\d+)").unwrap());
+    let duplicates: u64 = match re.captures(info) {
+        Some(captures) => captures[1].parse().chain_err(|| format!("Invalid info from server: '{}'", info))?,
+        None => bail!("Missing number of duplicates in info from server: '{}'", info)
+    };
+
+    let inserted = rows.saturating_sub(duplicates);
+    let updated = affected_rows.saturating_sub(inserted) / 2;
+
+    import_count.inserted += inserted;
+    import_count.updated += updated;
+    import_count.unchanged += duplicates.saturating_sub(updated);
+
+    Ok(())
+}
+
+/// Write the records one by one, one statement per record.
+pub fn import_rows(db_tx: &mut Transaction, table: Table, station_name: &str, columns: &[&str], records: &[Record]) -> Result<ImportCount> {
+    let mut import_count = ImportCount::default();
+    let query = upsert_batch_query(table, &upsert_columns(columns), 1);
+
+    info!("query: '{}'", query);
+
+    for record in records {
+        db_tx.exec_drop(&query, Params::Positional(record_params(station_name, record)))?;
+        count_upsert(&mut import_count, db_tx.affected_rows());
+    }
+
+    Ok(import_count)
+}
+
+/// Upsert query for the given number of rows with positional parameters.
+fn upsert_batch_query(table: Table, columns: &[&str], rows: usize) -> String {
+    let placeholders = format!("({})", vec!["?"; columns.len()].join(", "));
+    let values = vec![placeholders.as_str(); rows].join(",\n    ");
+    let updates = columns.iter()
+        .filter(|column| **column != "timestamp" && **column != "station")
+        .map(|column| format!("{} = VALUES({})", column, column))
+        .collect::<Vec<String>>()
+        .join(",\n    ");
+
+    format!("INSERT INTO {} ({}) VALUES\n    {}\nON DUPLICATE KEY UPDATE\n    {}", table.name(), columns.join(", "), values, updates)
+}
+
+/// Write the records with one multi-row statement per batch of batch_size records.
+pub fn import_batched(db_tx: &mut Transaction, table: Table, station_name: &str, columns: &[&str], records: &[Record],
+        batch_size: usize) -> Result<ImportCount> {
+    let mut import_count = ImportCount::default();
+    let columns = upsert_columns(columns);
+
+    for batch in records.chunks(batch_size) {
+        let query = upsert_batch_query(table, &columns, batch.len());
+        let values: Vec<Value> = batch.iter().flat_map(|record| record_params(station_name, record)).collect();
+
+        db_tx.exec_drop(query, Params::Positional(values))?;
+        count_batch_upsert(&mut import_count, batch.len() as u64, db_tx.affected_rows(), &db_tx.info_str())?;
+    }
+
+    info!("{} batched, records: {}, batch size: {}", table.name(), records.len(), batch_size);
+
+    Ok(import_count)
+}
+
+/// Add the unique key on (station, timestamp) to the table, needed for the upsert queries.
+/// Existing duplicate rows are reported and have to be removed manually before the key can be added.
+fn add_unique_key(db_conn: &mut PooledConn, table: Table) -> Result<()> {
+    let query = format!("SHOW INDEX FROM {} WHERE Key_name = 'station_timestamp'", table.name());
+    let index: Vec<Row> = db_conn.query(query)?;
+
+    if !index.is_empty() {
+        info!("Unique key station_timestamp already exists in table {}", table.name());
+        return Ok(())
+    }
+
+    let query = format!("SELECT station, timestamp, COUNT(*) FROM {}
+        GROUP BY station, timestamp HAVING COUNT(*) > 1
+        ORDER BY station, timestamp", table.name());
+    let duplicates: Vec<(Option<String>, Option<NaiveDateTime>, u64)> = db_conn.query(query)?;
+
+    if !duplicates.is_empty() {
+        for (station, timestamp, count) in &duplicates {
+            error!("Duplicate rows in table {}: station: {:?}, timestamp: {:?}, count: {}", table.name(), station, timestamp, count);
+        }
+
+        bail!("Table {} contains {} duplicate (station, timestamp) entries, remove them before adding the unique key",
+            table.name(), duplicates.len());
+    }
+
+    let query = format!("ALTER TABLE {} ADD UNIQUE KEY station_timestamp (station, timestamp)", table.name());
+    info!("query: '{}'", query);
+    db_conn.query_drop(query)?;
+
+    info!("Unique key station_timestamp added to table {}", table.name());
+
+    Ok(())
+}
+
+/// Add the column sbd_message_id to the table, skipped if it already exists.
+fn add_sbd_message_id(db_conn: &mut PooledConn, table: Table) -> Result<()> {
+    let exists: Option<u64> = db_conn.exec_first("SELECT COUNT(*) FROM information_schema.columns
+        WHERE table_schema = DATABASE() AND table_name = :table AND column_name = 'sbd_message_id'", params!{"table" => table.name()})?;
+
+    if exists.unwrap_or(0) > 0 {
+        info!("Column sbd_message_id already exists in table {}", table.name());
+        return Ok(())
+    }
+
+    let query = format!("ALTER TABLE {} ADD COLUMN sbd_message_id INT UNSIGNED, ADD KEY sbd_message_id (sbd_message_id)", table.name());
+    info!("query: '{}'", query);
+    db_conn.query_drop(query)?;
+
+    Ok(())
+}
+
+fn db_opts(db_config: &DbConfig, db_password: Option<&str>) -> OptsBuilder {
+    let mut db_builder = OptsBuilder::new()
+        .ip_or_hostname(Some(db_config.host.clone()))
+        .tcp_port(db_config.port.unwrap_or(3306))
+        .socket(db_config.socket.clone())
+        .db_name(Some(db_config.name.clone()))
+        .user(db_config.user.clone())
+        .pass(db_password);
+
+    if db_config.tls {
+        let ssl_opts = SslOpts::default()
+            .with_root_cert_path(db_config.tls_ca.as_ref().map(PathBuf::from))
+            .with_danger_skip_domain_validation(db_config.tls_skip_domain_validation);
+
+        db_builder = db_builder.ssl_opts(Some(ssl_opts));
+    }
+
+    db_builder
+}
+
+pub struct MySqlStorage {
+    db_conn: PooledConn,
+    batch_size: usize,
+}
+
+pub struct MySqlTransaction<'a> {
+    db_tx: Transaction<'a>,
+    batch_size: usize,
+}
+
+impl MySqlStorage {
+    pub fn connect(db_config: &DbConfig, db_password: Option<&str>) -> Result<MySqlStorage> {
+        let db_pool = Pool::new(db_opts(db_config, db_password))?;
+        let db_conn = db_pool.get_conn()?;
+
+        info!("Connected to MySQL database '{}' on {}", db_config.name, db_config.host);
+
+        Ok(MySqlStorage {
+            db_conn,
+            batch_size: db_config.batch_size,
+        })
+    }
+
+    /// Start a transaction on the underlying connection, used by the benchmark.
+    pub fn start_transaction(&mut self) -> Result<Transaction<'_>> {
+        Ok(self.db_conn.start_transaction(TxOpts::default())?)
+    }
+}
+
+impl Storage for MySqlStorage {
+    fn schema_version(&mut self) -> Result<u32> {
+        let exists: Option<u64> = self.db_conn.query_first("SELECT COUNT(*) FROM information_schema.tables
+            WHERE table_schema = DATABASE() AND table_name = 'schema_version'")?;
+
+        if exists.unwrap_or(0) == 0 {
+            return Ok(0)
+        }
+
+        let version: Option<u32> = self.db_conn.query_first("SELECT COALESCE(MAX(version), 0) FROM schema_version")?;
+
+        Ok(version.unwrap_or(0))
+    }
+
+    // DDL statements can not be rolled back in MySQL, so every migration has to be safe to run again
+    // if it fails before the version is recorded.
+    fn apply_migration(&mut self, migration: &Migration) -> Result<()> {
+        self.db_conn.query_drop(CREATE_SCHEMA_VERSION)?;
+
+        match migration.version {
+            1 => {
+                self.db_conn.query_drop(CREATE_BATTERY_DATA)?;
+                self.db_conn.query_drop(CREATE_MULTIPLE_DATA)?;
+            },
+            2 => {
+                add_unique_key(&mut self.db_conn, Table::BatteryData)?;
+                add_unique_key(&mut self.db_conn, Table::MultipleData)?;
+            },
+            3 => {
+                self.db_conn.query_drop(CREATE_SBD_MESSAGES)?;
+                add_sbd_message_id(&mut self.db_conn, Table::BatteryData)?;
+                add_sbd_message_id(&mut self.db_conn, Table::MultipleData)?;
+            },
+            4 => {
+                self.db_conn.query_drop(CREATE_RAW_MESSAGES)?;
+            },
+            5 => {
+                self.db_conn.query_drop(ALLOW_RAW_MESSAGES_WITHOUT_STATION)?;
+            },
+            version => bail!("Unknown migration for MySQL: {}", version)
+        }
+
+        self.db_conn.exec_drop("INSERT INTO schema_version (version, description, applied_at) VALUES (:version, :description, NOW())",
+            params!{"version" => migration.version, "description" => migration.description})?;
+
+        Ok(())
+    }
+
+    fn get_id(&mut self, table: Table, station_name: &str, date_time: NaiveDateTime) -> Result<Option<u64>> {
+        // select id, battery_voltage from battery_data where timestamp = '2017-10-05 00:00:00' and station = 'Santa_Gracia';
+        let query = format!("SELECT id FROM {} WHERE timestamp = :timestamp AND station = :station", table.name());
+
+        let result: Vec<u64> = self.db_conn.exec(query, params!{"timestamp" => date_time, "station" => station_name})?;
+
+        match result.len() {
+            0 => Ok(None),
+            1 => Ok(Some(result[0])),
+            _ => bail!("expected exactly one id from database but got: {:?}", result)
+        }
+    }
+
+    fn get_values(&mut self, table: Table, columns: &[&str], station_name: &str, date_time: NaiveDateTime) -> Result<Option<Vec<Option<f64>>>> {
+        let query = format!("SELECT {} FROM {} WHERE timestamp = :timestamp AND station = :station", columns.join(", "), table.name());
+
+        let row: Option<Row> = self.db_conn.exec_first(query, params!{"timestamp" => date_time, "station" => station_name})?;
+
+        match row {
+            Some(mut row) => {
+                let mut values = Vec::new();
+                for (i, column) in columns.iter().enumerate() {
+                    let value: Option<f64> = row.take_opt(i).unwrap_or(Ok(None))
+                        .chain_err(|| format!("Invalid value in column {} of table {}", column, table.name()))?;
+                    values.push(value);
+                }
+                Ok(Some(values))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn is_imported(&mut self, sha256: &str) -> Result<bool> {
+        let imported: Option<u64> = self.db_conn.exec_first("SELECT COUNT(*) FROM raw_messages WHERE sha256 = :sha256 AND parse_status = 'ok'",
+            params!{"sha256" => sha256})?;
+
+        Ok(imported.unwrap_or(0) > 0)
+    }
+
+    fn raw_messages(&mut self, failed_only: bool, station_name: Option<&str>) -> Result<Vec<StoredRawMessage>> {
+        let rows: Vec<Row> = self.db_conn.exec("SELECT id, sbd_message_id, sha256, imei, momsn, station, file_name, received_at, payload
+            FROM raw_messages
+            WHERE (NOT :failed_only OR parse_status <> 'ok') AND (:station IS NULL OR station = :station)
+            ORDER BY received_at, id", params!{"failed_only" => failed_only, "station" => station_name})?;
+
+        let mut messages = Vec::new();
+
+        for mut row in rows {
+            let invalid = || "Invalid row in table raw_messages";
+
+            messages.push(StoredRawMessage {
+                id: row.take_opt(0).unwrap_or(Ok(0)).chain_err(invalid)?,
+                sbd_message_id: row.take_opt(1).unwrap_or(Ok(None)).chain_err(invalid)?,
+                raw_message: RawMessage {
+                    sha256: row.take_opt(2).unwrap_or(Ok(String::new())).chain_err(invalid)?,
+                    imei: row.take_opt(3).unwrap_or(Ok(None)).chain_err(invalid)?,
+                    momsn: row.take_opt(4).unwrap_or(Ok(None)).chain_err(invalid)?,
+                    station: row.take_opt(5).unwrap_or(Ok(None)).chain_err(invalid)?,
+                    file_name: row.take_opt(6).unwrap_or(Ok(String::new())).chain_err(invalid)?,
+                    received_at: row.take_opt(7).unwrap_or(Ok(NaiveDateTime::default())).chain_err(invalid)?,
+                    payload: row.take_opt(8).unwrap_or(Ok(Vec::new())).chain_err(invalid)?,
+                },
+            });
+        }
+
+        Ok(messages)
+    }
+
+    fn transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
+        Ok(Box::new(MySqlTransaction {
+            db_tx: self.db_conn.start_transaction(TxOpts::default())?,
+            batch_size: self.batch_size,
+        }))
+    }
+}
+
+impl<'a> StorageTransaction for MySqlTransaction<'a> {
+    fn upsert_records(&mut self, table: Table, station_name: &str, columns: &[&str], records: &[Record]) -> Result<ImportCount> {
+        if self.batch_size > 1 {
+            import_batched(&mut self.db_tx, table, station_name, columns, records, self.batch_size)
+        } else {
+            import_rows(&mut self.db_tx, table, station_name, columns, records)
+        }
+    }
+
+    fn upsert_sbd_message(&mut self, station_name: &str, message: &SbdMessage) -> Result<u64> {
+        self.db_tx.exec_drop(UPSERT_SBD_MESSAGE, params!{
+            "imei" => &message.imei,
+            "momsn" => message.momsn,
+            "mtmsn" => message.mtmsn,
+            "station" => station_name,
+            "session_time" => message.session_time,
+            "session_status" => &message.session_status,
+            "message_size" => message.message_size,
+            "latitude" => message.latitude,
+            "longitude" => message.longitude,
+            "cep_radius" => message.cep_radius,
+        })?;
+
+        match self.db_tx.last_insert_id() {
+            Some(id) => Ok(id),
+            None => bail!("No id returned for SBD message: {} {}", message.imei, message.momsn)
+        }
+    }
+
+    fn link_sbd_message(&mut self, table: Table, station_name: &str, date_times: &[NaiveDateTime], sbd_message_id: u64) -> Result<()> {
+        let query = format!("UPDATE {} SET sbd_message_id = :sbd_message_id WHERE timestamp = :timestamp AND station = :station", table.name());
+
+        for date_time in date_times {
+            self.db_tx.exec_drop(&query, params!{"sbd_message_id" => sbd_message_id, "timestamp" => date_time, "station" => station_name})?;
+        }
+
+        Ok(())
+    }
+
+    fn upsert_raw_message(&mut self, raw_message: &RawMessage, sbd_message_id: Option<u64>, parse_status: ParseStatus,
+            parse_error: Option<&str>) -> Result<()> {
+        self.db_tx.exec_drop(UPSERT_RAW_MESSAGE, params!{
+            "sha256" => &raw_message.sha256,
+            "imei" => &raw_message.imei,
+            "momsn" => raw_message.momsn,
+            "station" => &raw_message.station,
+            "file_name" => &raw_message.file_name,
+            "received_at" => raw_message.received_at,
+            "payload" => &raw_message.payload,
+            "parse_status" => parse_status.name(),
+            "parse_error" => parse_error,
+            "sbd_message_id" => sbd_message_id,
+        })?;
+
+        Ok(())
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        Ok(self.db_tx.commit()?)
+    }
+
+    fn rollback(self: Box<Self>) -> Result<()> {
+        Ok(self.db_tx.rollback()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration};
+    use mysql::prelude::{Queryable};
+
+    use data_parser::{Record};
+    use database::{Table, ImportCount};
+    use db_config::{DbConfig};
+    use password::{read_password};
+    use test_data::{date_time};
+
+    use super::{MySqlStorage, upsert_batch_query, count_batch_upsert, import_rows, import_batched};
+
+    /// Needs a MySQL server with the weatherstation tables, configured like the benchmark with SBD_DB_HOST, SBD_DB_NAME, ...
+    /// Everything is rolled back. SBD_DB_NAME=weatherstation_test cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn test_station_name_with_quotes_round_trip() {
+        let mut db_config = DbConfig::default();
+        db_config.override_from_env().unwrap();
+        let db_password = read_password(&db_config, None).unwrap();
+        let mut storage = MySqlStorage::connect(&db_config, db_password.as_deref()).unwrap();
+        let mut db_tx = storage.start_transaction().unwrap();
+
+        let station_name = "O'Higgins\\' OR '1'='1";
+        let columns = ["battery_voltage", "li_battery_voltage", "wind_dir"];
+        let records: Vec<Record> = (0..2).map(|hour| Record {
+            offset: 0,
+            date_time: date_time() + Duration::hours(hour),
+            values: vec![12.76, 0.0, 0.0],
+        }).collect();
+
+        import_rows(&mut db_tx, Table::BatteryData, station_name, &columns, &records[0..1]).unwrap();
+        let import_count = import_batched(&mut db_tx, Table::BatteryData, station_name, &columns, &records, 2).unwrap();
+
+        let stations: Vec<String> = db_tx.exec("SELECT station FROM battery_data WHERE station = ? ORDER BY timestamp", (station_name,)).unwrap();
+
+        db_tx.rollback().unwrap();
+
+        assert_eq!(stations, vec![station_name.to_string(); 2]);
+        assert_eq!(import_count, ImportCount { inserted: 1, updated: 0, unchanged: 1 });
+    }
+
+    #[test]
+    fn test_upsert_batch_query() {
+        let query = upsert_batch_query(Table::MultipleData, &["timestamp", "station", "wind_speed"], 2);
+
+        assert_eq!(query, "INSERT INTO multiple_data (timestamp, station, wind_speed) VALUES
+    (?, ?, ?),
+    (?, ?, ?)
+ON DUPLICATE KEY UPDATE
+    wind_speed = VALUES(wind_speed)");
+    }
+
+    #[test]
+    fn test_import_count_batch() {
+        let mut import_count = ImportCount::default();
+
+        // 10 rows: 5 new, 3 changed, 2 unchanged
+        count_batch_upsert(&mut import_count, 10, 5 + 2 * 3, "Records: 10  Duplicates: 5  Warnings: 0").unwrap();
+
+        assert_eq!(import_count, ImportCount { inserted: 5, updated: 3, unchanged: 2 });
+        assert!(count_batch_upsert(&mut import_count, 10, 10, "").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+// External modules:
+use serde_json::{self, Map, Value};
+
+// System modules:
+use std::str::FromStr;
+
+// Internal modules:
+use error::{Error, Result};
+use data_parser::{Record, WeatherStationData};
+
+/// How decoded SBD data is printed by the parse subcommand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<OutputFormat> {
+        match format {
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            _ => bail!("Unknown output format: '{}', expected json, csv or table", format)
+        }
+    }
+}
+
+/// The date_time and the values of one record by field name, in the order of the record definition.
+fn record_object(data: &WeatherStationData, record: &Record) -> Result<Map<String, Value>> {
+    let mut object = Map::new();
+    object.insert("date_time".to_string(), serde_json::to_value(record.date_time)?);
+
+    for (field, value) in data.definition.fields.iter().zip(&record.values) {
+        object.insert(field.name.clone(), serde_json::to_value(value)?);
+    }
+
+    Ok(object)
+}
+
+/// The header and the rows of all records in the data.
+fn rows(data: &WeatherStationData) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let header = Some("date_time".to_string()).into_iter()
+        .chain(data.definition.fields.iter().map(|field| field.name.clone()))
+        .collect();
+
+    let rows = data.records.iter().map(|record| {
+        let row = record_object(data, record)?.values().map(|value| match *value {
+            Value::String(ref value) => value.clone(),
+            ref value => value.to_string(),
+        }).collect();
+        Ok(row)
+    }).collect::<Result<Vec<_>>>()?;
+
+    Ok((header, rows))
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn format_csv(header: &[String], rows: &[Vec<String>]) -> String {
+    let mut result = String::new();
+
+    for line in Some(header).into_iter().chain(rows.iter().map(|row| row.as_slice())) {
+        let fields: Vec<String> = line.iter().map(|field| csv_field(field)).collect();
+        result.push_str(&fields.join(","));
+        result.push('\n');
+    }
+
+    result
+}
+
+fn format_table(header: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|column| column.len()).collect();
+
+    for row in rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.len());
+        }
+    }
+
+    let format_line = |line: &[String]| {
+        let fields: Vec<String> = line.iter().zip(&widths).map(|(field, width)| format!("{:>1$}", field, width)).collect();
+        format!("| {} |\n", fields.join(" | "))
+    };
+
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+
+    let mut result = format_line(header);
+    result.push_str(&format!("|-{}-|\n", separator.join("-|-")));
+
+    for row in rows {
+        result.push_str(&format_line(row));
+    }
+
+    result
+}
+
+/// Print the decoded data in the given format.
+pub fn format_data(data: &WeatherStationData, format: OutputFormat) -> Result<String> {
+    match format {
+        OutputFormat::Json => {
+            let mut object = Map::new();
+            object.insert("record".to_string(), Value::String(data.definition.name.clone()));
+            object.insert("detection".to_string(), Value::String(data.detection.to_string()));
+            object.insert("table".to_string(), Value::String(data.definition.table.name().to_string()));
+            object.insert("records".to_string(), Value::Array(data.records.iter()
+                .map(|record| record_object(data, record).map(Value::Object))
+                .collect::<Result<Vec<_>>>()?));
+
+            let mut result = serde_json::to_string_pretty(&object)?;
+            result.push('\n');
+            Ok(result)
+        },
+        OutputFormat::Csv => {
+            let (header, rows) = rows(data)?;
+            Ok(format_csv(&header, &rows))
+        },
+        OutputFormat::Table => {
+            let (header, rows) = rows(data)?;
+            Ok(format_table(&header, &rows))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_parser::{parse_data};
+    use test_data::{SIMPLE, received_at};
+    use layout::{builtin_layout};
+
+    use super::{OutputFormat, format_data};
+
+    #[test]
+    fn test_format_data() {
+        let data = parse_data(&SIMPLE, &builtin_layout("simple_and_multiple").unwrap(), received_at()).unwrap();
+
+        assert_eq!(format_data(&data, OutputFormat::Csv).unwrap(),
+            "date_time,solar_battery_voltage,lithium_battery_voltage,wind_direction\n2016-09-19T00:00:00,12.76,0.0,0.0\n");
+
+        assert_eq!(format_data(&data, OutputFormat::Table).unwrap(),
+"|           date_time | solar_battery_voltage | lithium_battery_voltage | wind_direction |
+|---------------------|-----------------------|-------------------------|----------------|
+| 2016-09-19T00:00:00 |                 12.76 |                     0.0 |            0.0 |
+");
+
+        let json = format_data(&data, OutputFormat::Json).unwrap();
+        assert!(json.starts_with("{\n  \"record\": \"simple\",\n  \"detection\": \"exact length\",\n  \"table\": \"battery_data\",\n  \"records\": [\n    {\n      \"date_time\": \"2016-09-19T00:00:00\",\n"));
+    }
+}
@@ -0,0 +1,211 @@
+// System modules:
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::os::unix::fs::PermissionsExt;
+
+// Internal modules:
+use error::{Result, ResultExt};
+use db_config::{DbConfig};
+
+/// Find the password for the database, in this order:
+/// 1. The password file given in the config (--db_password_file, SBD_DB_PASSWORD_FILE)
+/// 2. The environment variable SBD_DB_PASSWORD
+/// 3. The [client] section of a MySQL option file, by default ~/.my.cnf
+/// 4. The --db_password command line option (discouraged, visible in ps and shell history)
+pub fn read_password(db_config: &DbConfig, cli_password: Option<&str>) -> Result<Option<String>> {
+    let default_option_file = env::var("HOME").ok().map(|home| Path::new(&home).join(".my.cnf"));
+
+    find_password(db_config, default_option_file.as_deref(), cli_password)
+}
+
+fn find_password(db_config: &DbConfig, default_option_file: Option<&Path>, cli_password: Option<&str>) -> Result<Option<String>> {
+    let password = if let Some(ref password_file) = db_config.password_file {
+        info!("Using database password from file: '{}'", password_file);
+        Some(read_password_file(Path::new(password_file))?)
+    } else if let Ok(password) = env::var("SBD_DB_PASSWORD") {
+        info!("Using database password from environment variable SBD_DB_PASSWORD");
+        Some(password)
+    } else {
+        read_option_file_password(db_config, default_option_file)?
+    };
+
+    match (password, cli_password) {
+        (Some(password), Some(_)) => {
+            warn!("Ignoring password given with --db_password, another password source has priority");
+            Ok(Some(password))
+        },
+        (Some(password), None) => {
+            Ok(Some(password))
+        },
+        (None, Some(password)) => {
+            warn!("Password given on command line with --db_password, use a password file, SBD_DB_PASSWORD or ~/.my.cnf instead");
+            Ok(Some(password.to_string()))
+        },
+        (None, None) => {
+            warn!("No database password given");
+            Ok(None)
+        }
+    }
+}
+
+/// Read the password from the first line of the file.
+/// The file must not be accessible by group or others.
+pub fn read_password_file(path: &Path) -> Result<String> {
+    check_permissions(path)?;
+
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .chain_err(|| format!("Could not read password file: '{}'", path.display()))?;
+
+    match content.lines().next() {
+        Some(password) if !password.is_empty() => Ok(password.to_string()),
+        _ => bail!("Password file is empty: '{}'", path.display())
+    }
+}
+
+fn check_permissions(path: &Path) -> Result<()> {
+    let metadata = path.metadata().chain_err(|| format!("Could not access file: '{}'", path.display()))?;
+    let mode = metadata.permissions().mode();
+
+    if mode & 0o077 != 0 {
+        bail!("File '{}' is accessible by group or others (mode {:o}), use: chmod 600 '{}'", path.display(), mode & 0o777, path.display());
+    }
+
+    Ok(())
+}
+
+/// An option file given in the config must be protected, the default option file
+/// is only a fallback and ignored with a warning if it is not.
+fn read_option_file_password(db_config: &DbConfig, default_option_file: Option<&Path>) -> Result<Option<String>> {
+    let (option_file, configured) = match (db_config.option_file.as_ref(), default_option_file) {
+        (Some(option_file), _) => (PathBuf::from(option_file), true),
+        (None, Some(default_option_file)) => (default_option_file.to_path_buf(), false),
+        (None, None) => return Ok(None)
+    };
+
+    if !option_file.exists() {
+        return Ok(None)
+    }
+
+    if let Err(e) = check_permissions(&option_file) {
+        if configured {
+            return Err(e);
+        }
+
+        warn!("Ignoring option file: {}", e);
+        return Ok(None)
+    }
+
+    let mut content = String::new();
+    File::open(&option_file)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .chain_err(|| format!("Could not read option file: '{}'", option_file.display()))?;
+
+    let password = parse_option_file_password(&content);
+
+    if password.is_some() {
+        info!("Using database password from option file: '{}'", option_file.display());
+    }
+
+    Ok(password)
+}
+
+/// Extract the password from the [client] section of a MySQL option file (my.cnf).
+fn parse_option_file_password(content: &str) -> Option<String> {
+    let mut in_client_section = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_client_section = line == "[client]";
+            continue;
+        }
+
+        if in_client_section {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+
+            if key == "password" {
+                let value = parts.next().unwrap_or("").trim();
+                let value = value.trim_matches('"').trim_matches('\'');
+                return Some(value.to_string());
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    use db_config::{DbConfig};
+
+    use super::{find_password, parse_option_file_password, read_password_file};
+
+    #[test]
+    fn test_parse_option_file_password() {
+        let content = "
+            [mysql]
+            password = wrong
+
+            # Credentials for the importer
+            [client]
+            user = sbd_import
+            password = \"se=cret\"
+        ";
+
+        assert_eq!(parse_option_file_password(content), Some("se=cret".to_string()));
+        assert_eq!(parse_option_file_password("[mysql]\npassword=wrong\n"), None);
+    }
+
+    #[test]
+    fn test_read_password_file() {
+        let path = env::temp_dir().join(format!("sbd_db_import_password_{}", ::std::process::id()));
+
+        {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(b"secret\n").unwrap();
+        }
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(read_password_file(&path).is_err());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_password_file(&path).unwrap(), "secret");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unprotected_default_option_file_is_ignored() {
+        let path = env::temp_dir().join(format!("sbd_db_import_my_cnf_{}", ::std::process::id()));
+
+        {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(b"[client]\npassword = from_option_file\n").unwrap();
+        }
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let db_config = DbConfig::default();
+        assert_eq!(find_password(&db_config, Some(&path), Some("from_cli")).unwrap(), Some("from_cli".to_string()));
+
+        let db_config = DbConfig { option_file: Some(path.to_string_lossy().into_owned()), .. DbConfig::default() };
+        assert!(find_password(&db_config, None, Some("from_cli")).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
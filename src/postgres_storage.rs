@@ -0,0 +1,448 @@
+// External modules:
+use chrono::{NaiveDateTime};
+use postgres::{Client, Config, NoTls, Transaction};
+use postgres::types::{ToSql};
+use postgres::{ToStatement};
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::{MakeTlsConnector};
+
+// System modules:
+use std::fs::File;
+use std::io::Read;
+
+// Internal modules:
+use error::{Result, ResultExt};
+use data_parser::{Record};
+use db_config::{DbConfig};
+use database::{Table, ImportCount, ParseStatus, RawMessage, StoredRawMessage, Storage, StorageTransaction};
+use migration::{Migration};
+use email::{SbdMessage};
+
+const CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TIMESTAMP NOT NULL
+)";
+
+// TimescaleDB needs the time column in every unique index of a hypertable,
+// so the id is not a primary key here, (station, timestamp) identifies a record.
+
+const CREATE_BATTERY_DATA: &str = "CREATE TABLE IF NOT EXISTS battery_data (
+    id BIGSERIAL,
+    timestamp TIMESTAMP NOT NULL,
+    station VARCHAR(32) NOT NULL,
+    battery_voltage DOUBLE PRECISION,
+    li_battery_voltage DOUBLE PRECISION,
+    wind_dir DOUBLE PRECISION
+)";
+
+const CREATE_MULTIPLE_DATA: &str = "CREATE TABLE IF NOT EXISTS multiple_data (
+    id BIGSERIAL,
+    timestamp TIMESTAMP NOT NULL,
+    station VARCHAR(32) NOT NULL,
+    air_temperature DOUBLE PRECISION,
+    air_relative_humidity DOUBLE PRECISION,
+    solar_radiation DOUBLE PRECISION,
+    soil_water_content DOUBLE PRECISION,
+    soil_temperature DOUBLE PRECISION,
+    wind_speed DOUBLE PRECISION,
+    wind_max DOUBLE PRECISION,
+    wind_direction DOUBLE PRECISION,
+    precipitation DOUBLE PRECISION,
+    air_pressure DOUBLE PRECISION
+)";
+
+const CREATE_UNIQUE_KEYS: &str = "
+    CREATE UNIQUE INDEX IF NOT EXISTS battery_data_station_timestamp ON battery_data (station, timestamp);
+    CREATE UNIQUE INDEX IF NOT EXISTS multiple_data_station_timestamp ON multiple_data (station, timestamp);
+";
+
+const CREATE_SBD_MESSAGES: &str = "
+    CREATE TABLE IF NOT EXISTS sbd_messages (
+        id BIGSERIAL PRIMARY KEY,
+        imei VARCHAR(15) NOT NULL,
+        momsn INTEGER NOT NULL,
+        mtmsn INTEGER,
+        station VARCHAR(32),
+        session_time TIMESTAMP,
+        session_status VARCHAR(64),
+        message_size INTEGER,
+        latitude DOUBLE PRECISION,
+        longitude DOUBLE PRECISION,
+        cep_radius DOUBLE PRECISION
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS sbd_messages_imei_momsn ON sbd_messages (imei, momsn);
+    ALTER TABLE battery_data ADD COLUMN IF NOT EXISTS sbd_message_id BIGINT REFERENCES sbd_messages (id);
+    ALTER TABLE multiple_data ADD COLUMN IF NOT EXISTS sbd_message_id BIGINT REFERENCES sbd_messages (id);
+";
+
+const UPSERT_SBD_MESSAGE: &str = "INSERT INTO sbd_messages (
+    imei,
+    momsn,
+    mtmsn,
+    station,
+    session_time,
+    session_status,
+    message_size,
+    latitude,
+    longitude,
+    cep_radius
+) VALUES (
+    $1, $2, $3, $4, $5, $6, $7, $8, $9, $10
+) ON CONFLICT (imei, momsn) DO UPDATE SET
+    mtmsn = EXCLUDED.mtmsn,
+    station = EXCLUDED.station,
+    session_time = EXCLUDED.session_time,
+    session_status = EXCLUDED.session_status,
+    message_size = EXCLUDED.message_size,
+    latitude = EXCLUDED.latitude,
+    longitude = EXCLUDED.longitude,
+    cep_radius = EXCLUDED.cep_radius
+RETURNING id";
+
+const CREATE_RAW_MESSAGES: &str = "CREATE TABLE IF NOT EXISTS raw_messages (
+    id BIGSERIAL PRIMARY KEY,
+    sha256 CHAR(64) NOT NULL UNIQUE,
+    imei VARCHAR(15),
+    momsn INTEGER,
+    station VARCHAR(32) NOT NULL,
+    file_name VARCHAR(255) NOT NULL,
+    received_at TIMESTAMP NOT NULL,
+    payload BYTEA NOT NULL,
+    parse_status VARCHAR(16) NOT NULL,
+    parse_error TEXT,
+    parsed_at TIMESTAMP NOT NULL,
+    sbd_message_id BIGINT REFERENCES sbd_messages (id)
+)";
+
+const ALLOW_RAW_MESSAGES_WITHOUT_STATION: &str = "ALTER TABLE raw_messages ALTER COLUMN station DROP NOT NULL";
+
+// The payload, IMEI, MOMSN, file name and receive time of the first import are kept,
+// the station only if it was known.
+
+const UPSERT_RAW_MESSAGE: &str = "INSERT INTO raw_messages (
+    sha256,
+    imei,
+    momsn,
+    station,
+    file_name,
+    received_at,
+    payload,
+    parse_status,
+    parse_error,
+    parsed_at,
+    sbd_message_id
+) VALUES (
+    $1, $2, $3, $4, $5, $6, $7, $8, $9, timezone('UTC', now()), $10
+) ON CONFLICT (sha256) DO UPDATE SET
+    station = COALESCE(raw_messages.station, EXCLUDED.station),
+    parse_status = EXCLUDED.parse_status,
+    parse_error = EXCLUDED.parse_error,
+    parsed_at = EXCLUDED.parsed_at,
+    sbd_message_id = COALESCE(EXCLUDED.sbd_message_id, raw_messages.sbd_message_id)";
+
+// Unchanged records are not updated and return no row,
+// xmax is 0 for a newly inserted row and the id of the transaction for an updated row.
+
+/// Insert a record or update its values if they changed, $1: timestamp, $2: station, $3...: the values of the columns.
+fn upsert_query(table: Table, columns: &[&str]) -> String {
+    let placeholders: Vec<String> = (0..columns.len()).map(|i| format!("${}", i + 3)).collect();
+    let updates: Vec<String> = columns.iter().map(|column| format!("{0} = EXCLUDED.{0}", column)).collect();
+    let stored: Vec<String> = columns.iter().map(|column| format!("{}.{}", table.name(), column)).collect();
+    let excluded: Vec<String> = columns.iter().map(|column| format!("EXCLUDED.{}", column)).collect();
+
+    // A single column is compared as a value, not as a row
+    format!("INSERT INTO {} (timestamp, station, {}) VALUES ($1, $2, {})
+        ON CONFLICT (station, timestamp) DO UPDATE SET {}
+        WHERE ({}) IS DISTINCT FROM ({})
+        RETURNING (xmax = 0) AS inserted",
+        table.name(), columns.join(", "), placeholders.join(", "), updates.join(", "), stored.join(", "), excluded.join(", "))
+}
+
+pub struct PostgresStorage {
+    db_client: Client,
+}
+
+pub struct PostgresTransaction<'a> {
+    db_tx: Transaction<'a>,
+}
+
+/// Run one upsert and count the result, see RETURNING in upsert_query().
+fn upsert<T>(db_tx: &mut Transaction, query: &T, params: &[&(dyn ToSql + Sync)], import_count: &mut ImportCount) -> Result<()> where T: ?Sized + ToStatement {
+    match db_tx.query_opt(query, params)? {
+        Some(row) => {
+            let inserted: bool = row.try_get(0)?;
+            if inserted {
+                import_count.inserted += 1;
+            } else {
+                import_count.updated += 1;
+            }
+        },
+        None => {
+            import_count.unchanged += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn tls_connector(db_config: &DbConfig) -> Result<MakeTlsConnector> {
+    let mut builder = TlsConnector::builder();
+
+    if let Some(ref tls_ca) = db_config.tls_ca {
+        let mut pem = Vec::new();
+        File::open(tls_ca)
+            .and_then(|mut file| file.read_to_end(&mut pem))
+            .chain_err(|| format!("Could not read CA certificate: '{}'", tls_ca))?;
+        builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+
+    builder.danger_accept_invalid_hostnames(db_config.tls_skip_domain_validation);
+
+    Ok(MakeTlsConnector::new(builder.build()?))
+}
+
+impl PostgresStorage {
+    /// Connect to the database.
+    pub fn connect(db_config: &DbConfig, db_password: Option<&str>) -> Result<PostgresStorage> {
+        let mut config = Config::new();
+        config.dbname(&db_config.name);
+
+        match db_config.socket {
+            // The socket is given as the directory containing it
+            Some(ref socket) => config.host_path(socket),
+            None => config.host(&db_config.host),
+        };
+
+        if let Some(port) = db_config.port {
+            config.port(port);
+        }
+
+        if let Some(ref user) = db_config.user {
+            config.user(user);
+        }
+
+        if let Some(password) = db_password {
+            config.password(password);
+        }
+
+        let db_client = if db_config.tls {
+            config.connect(tls_connector(db_config)?)?
+        } else {
+            config.connect(NoTls)?
+        };
+
+        info!("Connected to PostgreSQL database '{}' on {}", db_config.name, db_config.host);
+
+        Ok(PostgresStorage {
+            db_client,
+        })
+    }
+}
+
+/// Turn the tables into TimescaleDB hypertables partitioned by timestamp,
+/// skipped if the extension is not created in the database.
+/// Creating the extension needs a superuser, so it is not done here:
+/// CREATE EXTENSION timescaledb; has to be run in the database before init_db.
+fn create_hypertables(db_tx: &mut Transaction) -> Result<()> {
+    let row = db_tx.query_one("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb')", &[])?;
+    let created: bool = row.try_get(0)?;
+
+    if !created {
+        warn!("TimescaleDB extension not created in this database, using regular tables");
+        return Ok(())
+    }
+
+    for table in &[Table::BatteryData, Table::MultipleData] {
+        db_tx.execute("SELECT create_hypertable($1::text::regclass, 'timestamp', if_not_exists => TRUE, migrate_data => TRUE)",
+            &[&table.name()])?;
+    }
+
+    Ok(())
+}
+
+impl Storage for PostgresStorage {
+    fn schema_version(&mut self) -> Result<u32> {
+        let row = self.db_client.query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])?;
+        let exists: bool = row.try_get(0)?;
+
+        if !exists {
+            return Ok(0)
+        }
+
+        let row = self.db_client.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?;
+        let version: i32 = row.try_get(0)?;
+
+        Ok(version as u32)
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> Result<()> {
+        let mut db_tx = self.db_client.transaction()?;
+        db_tx.batch_execute(CREATE_SCHEMA_VERSION)?;
+
+        match migration.version {
+            1 => {
+                db_tx.batch_execute(CREATE_BATTERY_DATA)?;
+                db_tx.batch_execute(CREATE_MULTIPLE_DATA)?;
+                create_hypertables(&mut db_tx)?;
+            },
+            2 => {
+                db_tx.batch_execute(CREATE_UNIQUE_KEYS)?;
+            },
+            3 => {
+                db_tx.batch_execute(CREATE_SBD_MESSAGES)?;
+            },
+            4 => {
+                db_tx.batch_execute(CREATE_RAW_MESSAGES)?;
+            },
+            5 => {
+                db_tx.batch_execute(ALLOW_RAW_MESSAGES_WITHOUT_STATION)?;
+            },
+            version => bail!("Unknown migration for PostgreSQL: {}", version)
+        }
+
+        db_tx.execute("INSERT INTO schema_version (version, description, applied_at) VALUES ($1, $2, now())",
+            &[&(migration.version as i32), &migration.description])?;
+
+        Ok(db_tx.commit()?)
+    }
+
+    fn get_id(&mut self, table: Table, station_name: &str, date_time: NaiveDateTime) -> Result<Option<u64>> {
+        let query = format!("SELECT id FROM {} WHERE timestamp = $1 AND station = $2", table.name());
+        let rows = self.db_client.query(query.as_str(), &[&date_time, &station_name])?;
+
+        match rows.len() {
+            0 => Ok(None),
+            1 => {
+                let id: i64 = rows[0].try_get(0)?;
+                Ok(Some(id as u64))
+            },
+            _ => bail!("expected exactly one id from database but got: {}", rows.len())
+        }
+    }
+
+    fn get_values(&mut self, table: Table, columns: &[&str], station_name: &str, date_time: NaiveDateTime) -> Result<Option<Vec<Option<f64>>>> {
+        let query = format!("SELECT {} FROM {} WHERE timestamp = $1 AND station = $2", columns.join(", "), table.name());
+        let row = self.db_client.query_opt(query.as_str(), &[&date_time, &station_name])?;
+
+        match row {
+            Some(row) => {
+                let values = (0..columns.len()).map(|i| row.try_get(i))
+                    .collect::<::std::result::Result<Vec<Option<f64>>, postgres::Error>>()
+                    .chain_err(|| format!("Could not read the values of {} as DOUBLE PRECISION: {}", table.name(), columns.join(", ")))?;
+                Ok(Some(values))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn is_imported(&mut self, sha256: &str) -> Result<bool> {
+        let row = self.db_client.query_one("SELECT EXISTS (SELECT 1 FROM raw_messages WHERE sha256 = $1 AND parse_status = 'ok')", &[&sha256])?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    fn raw_messages(&mut self, failed_only: bool, station_name: Option<&str>) -> Result<Vec<StoredRawMessage>> {
+        let rows = self.db_client.query("SELECT id, sbd_message_id, sha256, imei, momsn, station, file_name, received_at, payload
+            FROM raw_messages
+            WHERE (NOT $1 OR parse_status <> 'ok') AND ($2::text IS NULL OR station = $2)
+            ORDER BY received_at, id", &[&failed_only, &station_name])?;
+
+        rows.iter().map(|row| {
+            let id: i64 = row.try_get(0)?;
+            let sbd_message_id: Option<i64> = row.try_get(1)?;
+            let momsn: Option<i32> = row.try_get(4)?;
+
+            Ok(StoredRawMessage {
+                id: id as u64,
+                sbd_message_id: sbd_message_id.map(|id| id as u64),
+                raw_message: RawMessage {
+                    sha256: row.try_get(2)?,
+                    imei: row.try_get(3)?,
+                    momsn: momsn.map(|momsn| momsn as u32),
+                    station: row.try_get(5)?,
+                    file_name: row.try_get(6)?,
+                    received_at: row.try_get(7)?,
+                    payload: row.try_get(8)?,
+                },
+            })
+        }).collect()
+    }
+
+    fn transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
+        Ok(Box::new(PostgresTransaction {
+            db_tx: self.db_client.transaction()?,
+        }))
+    }
+}
+
+impl<'a> StorageTransaction for PostgresTransaction<'a> {
+    fn upsert_records(&mut self, table: Table, station_name: &str, columns: &[&str], records: &[Record]) -> Result<ImportCount> {
+        let mut import_count = ImportCount::default();
+        let statement = self.db_tx.prepare(&upsert_query(table, columns))?;
+
+        for record in records {
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![&record.date_time, &station_name];
+            params.extend(record.values.iter().map(|value| value as &(dyn ToSql + Sync)));
+
+            upsert(&mut self.db_tx, &statement, &params, &mut import_count)?;
+        }
+
+        Ok(import_count)
+    }
+
+    fn upsert_sbd_message(&mut self, station_name: &str, message: &SbdMessage) -> Result<u64> {
+        let row = self.db_tx.query_one(UPSERT_SBD_MESSAGE, &[
+            &message.imei,
+            &(message.momsn as i32),
+            &message.mtmsn.map(|mtmsn| mtmsn as i32),
+            &station_name,
+            &message.session_time,
+            &message.session_status,
+            &message.message_size.map(|message_size| message_size as i32),
+            &message.latitude,
+            &message.longitude,
+            &message.cep_radius,
+        ])?;
+        let id: i64 = row.try_get(0)?;
+
+        Ok(id as u64)
+    }
+
+    fn link_sbd_message(&mut self, table: Table, station_name: &str, date_times: &[NaiveDateTime], sbd_message_id: u64) -> Result<()> {
+        let query = format!("UPDATE {} SET sbd_message_id = $1 WHERE timestamp = $2 AND station = $3", table.name());
+        let statement = self.db_tx.prepare(&query)?;
+
+        for date_time in date_times {
+            self.db_tx.execute(&statement, &[&(sbd_message_id as i64), date_time, &station_name])?;
+        }
+
+        Ok(())
+    }
+
+    fn upsert_raw_message(&mut self, raw_message: &RawMessage, sbd_message_id: Option<u64>, parse_status: ParseStatus,
+            parse_error: Option<&str>) -> Result<()> {
+        self.db_tx.execute(UPSERT_RAW_MESSAGE, &[
+            &raw_message.sha256,
+            &raw_message.imei,
+            &raw_message.momsn.map(|momsn| momsn as i32),
+            &raw_message.station,
+            &raw_message.file_name,
+            &raw_message.received_at,
+            &raw_message.payload,
+            &parse_status.name(),
+            &parse_error,
+            &sbd_message_id.map(|id| id as i64),
+        ])?;
+
+        Ok(())
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        Ok(self.db_tx.commit()?)
+    }
+
+    fn rollback(self: Box<Self>) -> Result<()> {
+        Ok(self.db_tx.rollback()?)
+    }
+}
@@ -0,0 +1,51 @@
+// System modules:
+use std::path::{PathBuf};
+
+// Internal modules:
+use error::{Result};
+use database::{Storage, reprocess_raw_message};
+use batch::{BatchSummary, FileResult};
+use station::{StationRegistry};
+
+/// Parse the payloads stored in raw_messages again with the current parser, e.g. after a parser fix.
+/// The station is looked up by name in the registry, so a changed layout is used as well.
+pub fn reprocess(storage: &mut dyn Storage, station_registry: &StationRegistry, station_name: Option<&str>,
+        failed_only: bool) -> Result<BatchSummary> {
+    let mut summary = BatchSummary::default();
+
+    for stored in storage.raw_messages(failed_only, station_name)? {
+        let label = PathBuf::from(&stored.raw_message.file_name);
+
+        // A payload skipped because of an unknown station has no station name, only the IMEI
+        let station = stored.raw_message.station.as_ref().and_then(|name| station_registry.find_by_name(name))
+            .or_else(|| stored.raw_message.imei.as_ref().and_then(|imei| station_registry.find_by_imei(imei)));
+
+        let result = match station {
+            Some(station) => match reprocess_raw_message(storage, station, &stored) {
+                Ok((import_count, problems)) => FileResult::Imported(import_count, problems),
+                Err(e) => FileResult::Failed(e.to_string()),
+            },
+            None => FileResult::Skipped(match stored.raw_message.station {
+                Some(ref station_name) => format!("Unknown station: '{}'", station_name),
+                None => format!("Unknown station, IMEI: {}", stored.raw_message.imei.as_deref().unwrap_or("none")),
+            }),
+        };
+
+        match result {
+            FileResult::Imported(import_count, ref problems) => {
+                info!("Reprocessed: '{}', inserted: {}, updated: {}, unchanged: {}",
+                    label.display(), import_count.inserted, import_count.updated, import_count.unchanged);
+                for problem in problems {
+                    warn!("Parse problem in: '{}': {}", label.display(), problem);
+                }
+            },
+            FileResult::Skipped(ref reason) => warn!("Skipped: '{}': {}", label.display(), reason),
+            FileResult::Failed(ref reason) => error!("Reprocessing failed: '{}': {}", label.display(), reason),
+            FileResult::Duplicate(_) => {},
+        }
+
+        summary.add(label, result);
+    }
+
+    Ok(summary)
+}
@@ -0,0 +1,568 @@
+// External modules:
+use chrono::{NaiveDateTime};
+use rusqlite::{Connection, OpenFlags, Transaction, OptionalExtension, ToSql};
+
+// Internal modules:
+use error::{Result, ResultExt};
+use data_parser::{Record};
+use database::{Table, ImportCount, ParseStatus, RawMessage, StoredRawMessage, Storage, StorageTransaction};
+use migration::{Migration};
+use email::{SbdMessage};
+
+const CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER PRIMARY KEY,
+    description TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+)";
+
+const CREATE_BATTERY_DATA: &str = "CREATE TABLE IF NOT EXISTS battery_data (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp TEXT,
+    station TEXT,
+    battery_voltage REAL,
+    li_battery_voltage REAL,
+    wind_dir REAL
+)";
+
+const CREATE_MULTIPLE_DATA: &str = "CREATE TABLE IF NOT EXISTS multiple_data (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp TEXT,
+    station TEXT,
+    air_temperature REAL,
+    air_relative_humidity REAL,
+    solar_radiation REAL,
+    soil_water_content REAL,
+    soil_temperature REAL,
+    wind_speed REAL,
+    wind_max REAL,
+    wind_direction REAL,
+    precipitation REAL,
+    air_pressure REAL
+)";
+
+const CREATE_UNIQUE_KEYS: &str = "
+    CREATE UNIQUE INDEX IF NOT EXISTS battery_data_station_timestamp ON battery_data (station, timestamp);
+    CREATE UNIQUE INDEX IF NOT EXISTS multiple_data_station_timestamp ON multiple_data (station, timestamp);
+";
+
+const CREATE_SBD_MESSAGES: &str = "
+    CREATE TABLE IF NOT EXISTS sbd_messages (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        imei TEXT NOT NULL,
+        momsn INTEGER NOT NULL,
+        mtmsn INTEGER,
+        station TEXT,
+        session_time TEXT,
+        session_status TEXT,
+        message_size INTEGER,
+        latitude REAL,
+        longitude REAL,
+        cep_radius REAL
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS sbd_messages_imei_momsn ON sbd_messages (imei, momsn);
+    ALTER TABLE battery_data ADD COLUMN sbd_message_id INTEGER REFERENCES sbd_messages (id);
+    ALTER TABLE multiple_data ADD COLUMN sbd_message_id INTEGER REFERENCES sbd_messages (id);
+";
+
+const UPSERT_SBD_MESSAGE: &str = "INSERT INTO sbd_messages (
+    imei,
+    momsn,
+    mtmsn,
+    station,
+    session_time,
+    session_status,
+    message_size,
+    latitude,
+    longitude,
+    cep_radius
+) VALUES (
+    :imei,
+    :momsn,
+    :mtmsn,
+    :station,
+    :session_time,
+    :session_status,
+    :message_size,
+    :latitude,
+    :longitude,
+    :cep_radius
+) ON CONFLICT (imei, momsn) DO UPDATE SET
+    mtmsn = excluded.mtmsn,
+    station = excluded.station,
+    session_time = excluded.session_time,
+    session_status = excluded.session_status,
+    message_size = excluded.message_size,
+    latitude = excluded.latitude,
+    longitude = excluded.longitude,
+    cep_radius = excluded.cep_radius";
+
+const CREATE_RAW_MESSAGES: &str = "
+    CREATE TABLE IF NOT EXISTS raw_messages (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        sha256 TEXT NOT NULL UNIQUE,
+        imei TEXT,
+        momsn INTEGER,
+        station TEXT NOT NULL,
+        file_name TEXT NOT NULL,
+        received_at TEXT NOT NULL,
+        payload BLOB NOT NULL,
+        parse_status TEXT NOT NULL,
+        parse_error TEXT,
+        parsed_at TEXT NOT NULL,
+        sbd_message_id INTEGER REFERENCES sbd_messages (id)
+    );
+";
+
+// SQLite can not drop NOT NULL from a column, the table is copied instead.
+const ALLOW_RAW_MESSAGES_WITHOUT_STATION: &str = "
+    CREATE TABLE raw_messages_new (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        sha256 TEXT NOT NULL UNIQUE,
+        imei TEXT,
+        momsn INTEGER,
+        station TEXT,
+        file_name TEXT NOT NULL,
+        received_at TEXT NOT NULL,
+        payload BLOB NOT NULL,
+        parse_status TEXT NOT NULL,
+        parse_error TEXT,
+        parsed_at TEXT NOT NULL,
+        sbd_message_id INTEGER REFERENCES sbd_messages (id)
+    );
+    INSERT INTO raw_messages_new SELECT * FROM raw_messages;
+    DROP TABLE raw_messages;
+    ALTER TABLE raw_messages_new RENAME TO raw_messages;
+";
+
+// The payload, IMEI, MOMSN, file name and receive time of the first import are kept,
+// the station only if it was known.
+
+const UPSERT_RAW_MESSAGE: &str = "INSERT INTO raw_messages (
+    sha256,
+    imei,
+    momsn,
+    station,
+    file_name,
+    received_at,
+    payload,
+    parse_status,
+    parse_error,
+    parsed_at,
+    sbd_message_id
+) VALUES (
+    :sha256,
+    :imei,
+    :momsn,
+    :station,
+    :file_name,
+    :received_at,
+    :payload,
+    :parse_status,
+    :parse_error,
+    datetime('now'),
+    :sbd_message_id
+) ON CONFLICT (sha256) DO UPDATE SET
+    station = COALESCE(station, excluded.station),
+    parse_status = excluded.parse_status,
+    parse_error = excluded.parse_error,
+    parsed_at = excluded.parsed_at,
+    sbd_message_id = COALESCE(excluded.sbd_message_id, sbd_message_id)";
+
+// The WHERE clause skips the update if nothing has changed, so the number of changed rows
+// tells if an existing record was updated or left as it is.
+
+pub struct SqliteStorage {
+    db_conn: Connection,
+}
+
+pub struct SqliteTransaction<'a> {
+    db_tx: Transaction<'a>,
+}
+
+fn get_id(db_conn: &Connection, table: Table, station_name: &str, date_time: NaiveDateTime) -> Result<Option<u64>> {
+    let query = format!("SELECT id FROM {} WHERE timestamp = :timestamp AND station = :station", table.name());
+
+    let id = db_conn.query_row(&query, named_params!{":timestamp": date_time, ":station": station_name}, |row| row.get(0))
+        .optional()?;
+
+    Ok(id)
+}
+
+/// Insert a record or update its values if they changed, ?1: timestamp, ?2: station, ?3...: the values of the columns.
+fn upsert_query(table: Table, columns: &[&str]) -> String {
+    let placeholders: Vec<String> = (0..columns.len()).map(|i| format!("?{}", i + 3)).collect();
+    let updates: Vec<String> = columns.iter().map(|column| format!("{0} = excluded.{0}", column)).collect();
+    let conditions: Vec<String> = columns.iter().map(|column| format!("{0} IS NOT excluded.{0}", column)).collect();
+
+    format!("INSERT INTO {} (timestamp, station, {}) VALUES (?1, ?2, {})
+        ON CONFLICT (station, timestamp) DO UPDATE SET {}
+        WHERE {}",
+        table.name(), columns.join(", "), placeholders.join(", "), updates.join(", "), conditions.join(" OR "))
+}
+
+/// Count one upsert, existed: the record was in the table before, changes: number of changed rows
+fn count_upsert(import_count: &mut ImportCount, existed: bool, changes: usize) {
+    if !existed {
+        import_count.inserted += 1;
+    } else if changes > 0 {
+        import_count.updated += 1;
+    } else {
+        import_count.unchanged += 1;
+    }
+}
+
+impl SqliteStorage {
+    /// Open the database file, it is created if it does not exist yet.
+    pub fn open(file_name: &str) -> Result<SqliteStorage> {
+        let storage = SqliteStorage {
+            db_conn: Connection::open(file_name)?,
+        };
+
+        info!("Opened SQLite database: '{}'", file_name);
+
+        Ok(storage)
+    }
+
+    /// Open an existing database file without write access, for --dry_run.
+    pub fn open_read_only(file_name: &str) -> Result<SqliteStorage> {
+        let storage = SqliteStorage {
+            db_conn: Connection::open_with_flags(file_name, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+                .chain_err(|| format!("Could not open SQLite database: '{}'", file_name))?,
+        };
+
+        info!("Opened SQLite database read only: '{}'", file_name);
+
+        Ok(storage)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<SqliteStorage> {
+        Ok(SqliteStorage {
+            db_conn: Connection::open_in_memory()?,
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn schema_version(&mut self) -> Result<u32> {
+        let exists: bool = self.db_conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+            [], |row| row.get(0))?;
+
+        if !exists {
+            return Ok(0)
+        }
+
+        let version = self.db_conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?;
+
+        Ok(version)
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> Result<()> {
+        let db_tx = self.db_conn.transaction()?;
+        db_tx.execute(CREATE_SCHEMA_VERSION, [])?;
+
+        match migration.version {
+            1 => {
+                db_tx.execute(CREATE_BATTERY_DATA, [])?;
+                db_tx.execute(CREATE_MULTIPLE_DATA, [])?;
+            },
+            2 => {
+                db_tx.execute_batch(CREATE_UNIQUE_KEYS)?;
+            },
+            3 => {
+                db_tx.execute_batch(CREATE_SBD_MESSAGES)?;
+            },
+            4 => {
+                db_tx.execute_batch(CREATE_RAW_MESSAGES)?;
+            },
+            5 => {
+                db_tx.execute_batch(ALLOW_RAW_MESSAGES_WITHOUT_STATION)?;
+            },
+            version => bail!("Unknown migration for SQLite: {}", version)
+        }
+
+        db_tx.execute("INSERT INTO schema_version (version, description, applied_at) VALUES (:version, :description, datetime('now'))",
+            named_params!{":version": migration.version, ":description": migration.description})?;
+
+        Ok(db_tx.commit()?)
+    }
+
+    fn get_id(&mut self, table: Table, station_name: &str, date_time: NaiveDateTime) -> Result<Option<u64>> {
+        get_id(&self.db_conn, table, station_name, date_time)
+    }
+
+    fn get_values(&mut self, table: Table, columns: &[&str], station_name: &str, date_time: NaiveDateTime) -> Result<Option<Vec<Option<f64>>>> {
+        let query = format!("SELECT {} FROM {} WHERE timestamp = :timestamp AND station = :station", columns.join(", "), table.name());
+
+        let values = self.db_conn.query_row(&query, named_params!{":timestamp": date_time, ":station": station_name}, |row| {
+            (0..columns.len()).map(|i| row.get(i)).collect::<rusqlite::Result<Vec<Option<f64>>>>()
+        }).optional()?;
+
+        Ok(values)
+    }
+
+    fn is_imported(&mut self, sha256: &str) -> Result<bool> {
+        let imported = self.db_conn.query_row("SELECT EXISTS (SELECT 1 FROM raw_messages WHERE sha256 = :sha256 AND parse_status = 'ok')",
+            named_params!{":sha256": sha256}, |row| row.get(0))?;
+
+        Ok(imported)
+    }
+
+    fn raw_messages(&mut self, failed_only: bool, station_name: Option<&str>) -> Result<Vec<StoredRawMessage>> {
+        let mut statement = self.db_conn.prepare("SELECT id, sbd_message_id, sha256, imei, momsn, station, file_name, received_at, payload
+            FROM raw_messages
+            WHERE (:failed_only = 0 OR parse_status <> 'ok') AND (:station IS NULL OR station = :station)
+            ORDER BY received_at, id")?;
+
+        let rows = statement.query_map(named_params!{":failed_only": failed_only, ":station": station_name}, |row| {
+            Ok(StoredRawMessage {
+                id: row.get(0)?,
+                sbd_message_id: row.get(1)?,
+                raw_message: RawMessage {
+                    sha256: row.get(2)?,
+                    imei: row.get(3)?,
+                    momsn: row.get(4)?,
+                    station: row.get(5)?,
+                    file_name: row.get(6)?,
+                    received_at: row.get(7)?,
+                    payload: row.get(8)?,
+                },
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<Vec<StoredRawMessage>>>()?)
+    }
+
+    fn transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
+        Ok(Box::new(SqliteTransaction {
+            db_tx: self.db_conn.transaction()?,
+        }))
+    }
+}
+
+impl<'a> StorageTransaction for SqliteTransaction<'a> {
+    fn upsert_records(&mut self, table: Table, station_name: &str, columns: &[&str], records: &[Record]) -> Result<ImportCount> {
+        let mut import_count = ImportCount::default();
+        let mut statement = self.db_tx.prepare(&upsert_query(table, columns))?;
+
+        for record in records {
+            let existed = get_id(&self.db_tx, table, station_name, record.date_time)?.is_some();
+
+            let mut params: Vec<&dyn ToSql> = vec![&record.date_time, &station_name];
+            params.extend(record.values.iter().map(|value| value as &dyn ToSql));
+
+            let changes = statement.execute(&*params)?;
+            count_upsert(&mut import_count, existed, changes);
+        }
+
+        Ok(import_count)
+    }
+
+    fn upsert_sbd_message(&mut self, station_name: &str, message: &SbdMessage) -> Result<u64> {
+        self.db_tx.execute(UPSERT_SBD_MESSAGE, named_params!{
+            ":imei": message.imei,
+            ":momsn": message.momsn,
+            ":mtmsn": message.mtmsn,
+            ":station": station_name,
+            ":session_time": message.session_time,
+            ":session_status": message.session_status,
+            ":message_size": message.message_size,
+            ":latitude": message.latitude,
+            ":longitude": message.longitude,
+            ":cep_radius": message.cep_radius,
+        })?;
+
+        // last_insert_rowid() is not set if the message was updated
+        let id = self.db_tx.query_row("SELECT id FROM sbd_messages WHERE imei = :imei AND momsn = :momsn",
+            named_params!{":imei": message.imei, ":momsn": message.momsn}, |row| row.get(0))?;
+
+        Ok(id)
+    }
+
+    fn link_sbd_message(&mut self, table: Table, station_name: &str, date_times: &[NaiveDateTime], sbd_message_id: u64) -> Result<()> {
+        let query = format!("UPDATE {} SET sbd_message_id = :sbd_message_id WHERE timestamp = :timestamp AND station = :station", table.name());
+        let mut statement = self.db_tx.prepare(&query)?;
+
+        for date_time in date_times {
+            statement.execute(named_params!{":sbd_message_id": sbd_message_id, ":timestamp": date_time, ":station": station_name})?;
+        }
+
+        Ok(())
+    }
+
+    fn upsert_raw_message(&mut self, raw_message: &RawMessage, sbd_message_id: Option<u64>, parse_status: ParseStatus,
+            parse_error: Option<&str>) -> Result<()> {
+        self.db_tx.execute(UPSERT_RAW_MESSAGE, named_params!{
+            ":sha256": raw_message.sha256,
+            ":imei": raw_message.imei,
+            ":momsn": raw_message.momsn,
+            ":station": raw_message.station,
+            ":file_name": raw_message.file_name,
+            ":received_at": raw_message.received_at,
+            ":payload": raw_message.payload,
+            ":parse_status": parse_status.name(),
+            ":parse_error": parse_error,
+            ":sbd_message_id": sbd_message_id,
+        })?;
+
+        Ok(())
+    }
+
+    fn commit(self: Box<Self>) -> Result<()> {
+        Ok(self.db_tx.commit()?)
+    }
+
+    fn rollback(self: Box<Self>) -> Result<()> {
+        Ok(self.db_tx.rollback()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_parser::{parse_data};
+    use test_data::{SIMPLE, MULTIPLE, date_time, received_at, station};
+    use database::{Table, ImportCount, RawMessage, Storage, archive_skipped, import_to_db, import_raw_to_db, reprocess_raw_message};
+    use email::{SbdMessage};
+    use migration::{MIGRATIONS, migrate};
+
+    use super::{SqliteStorage};
+
+    #[test]
+    fn test_import_pipeline() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station = station("Santa_Gracia");
+
+        let data = parse_data(&MULTIPLE, &station.layout, received_at()).unwrap();
+        let import_count = import_to_db(&mut storage, &station, data).unwrap();
+        assert_eq!(import_count, ImportCount { inserted: 1, updated: 0, unchanged: 0 });
+
+        let data = parse_data(&MULTIPLE, &station.layout, received_at()).unwrap();
+        let import_count = import_to_db(&mut storage, &station, data).unwrap();
+        assert_eq!(import_count, ImportCount { inserted: 0, updated: 0, unchanged: 1 });
+
+        let mut changed = MULTIPLE.to_vec();
+        changed[9] = 223;
+        let data = parse_data(&changed, &station.layout, received_at()).unwrap();
+        let import_count = import_to_db(&mut storage, &station, data).unwrap();
+        assert_eq!(import_count, ImportCount { inserted: 0, updated: 1, unchanged: 0 });
+
+        let data = parse_data(&SIMPLE, &station.layout, received_at()).unwrap();
+        let import_count = import_to_db(&mut storage, &station, data).unwrap();
+        assert_eq!(import_count, ImportCount { inserted: 1, updated: 0, unchanged: 0 });
+
+        assert_eq!(storage.get_id(Table::MultipleData, "Santa_Gracia", date_time()).unwrap(), Some(1));
+        assert_eq!(storage.get_id(Table::BatteryData, "Santa_Gracia", date_time()).unwrap(), Some(1));
+        assert_eq!(storage.get_id(Table::BatteryData, "La_Campana", date_time()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_station_name_with_quotes_is_stored_literally() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station_name = "O'Higgins\\' OR '1'='1";
+        let station = station(station_name);
+
+        let data = parse_data(&SIMPLE, &station.layout, received_at()).unwrap();
+        import_to_db(&mut storage, &station, data).unwrap();
+
+        let stored: String = storage.db_conn.query_row("SELECT station FROM battery_data", [], |row| row.get(0)).unwrap();
+        assert_eq!(stored, station_name);
+        assert_eq!(storage.get_id(Table::BatteryData, station_name, date_time()).unwrap(), Some(1));
+        assert_eq!(storage.get_id(Table::BatteryData, "O'Higgins", date_time()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_import_links_sbd_message() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station = station("Santa_Gracia");
+
+        let mut message = SbdMessage {
+            imei: "300025060007390".to_string(),
+            momsn: 2,
+            mtmsn: Some(0),
+            session_time: Some(date_time()),
+            session_status: Some("00 - Transfer OK".to_string()),
+            message_size: Some(28),
+            latitude: Some(-29.75922),
+            longitude: Some(-71.16284),
+            cep_radius: Some(4.0),
+        };
+
+        let raw_message = RawMessage::new("300025060007390_000002.sbd", Some("Santa_Gracia"), MULTIPLE.to_vec(), Some(&message));
+        import_raw_to_db(&mut storage, &station, &raw_message, Some(&message)).unwrap();
+
+        // The same message again only updates the metadata
+        message.cep_radius = Some(5.0);
+        import_raw_to_db(&mut storage, &station, &raw_message, Some(&message)).unwrap();
+
+        let (count, cep_radius): (u32, f64) = storage.db_conn.query_row("SELECT COUNT(*), MAX(cep_radius) FROM sbd_messages", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!((count, cep_radius), (1, 5.0));
+
+        let sbd_message_id: Option<u64> = storage.db_conn.query_row("SELECT sbd_message_id FROM multiple_data", [], |row| row.get(0)).unwrap();
+        assert_eq!(sbd_message_id, Some(1));
+    }
+
+    #[test]
+    fn test_raw_messages() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        migrate(&mut storage).unwrap();
+        let station = station("Santa_Gracia");
+
+        let raw_message = RawMessage::new("300025060007390_000001.sbd", Some("Santa_Gracia"), SIMPLE.to_vec(), None);
+        assert_eq!(raw_message.sha256.len(), 64);
+        assert_eq!(raw_message.momsn, Some(1));
+        assert!(!storage.is_imported(&raw_message.sha256).unwrap());
+
+        import_raw_to_db(&mut storage, &station, &raw_message, None).unwrap();
+        assert!(storage.is_imported(&raw_message.sha256).unwrap());
+
+        // A payload that can not be parsed is kept for reprocessing
+        let broken = RawMessage::new("300025060007390_000002.sbd", Some("Santa_Gracia"), vec![1, 2, 3], None);
+        assert!(import_raw_to_db(&mut storage, &station, &broken, None).is_err());
+        assert!(!storage.is_imported(&broken.sha256).unwrap());
+
+        let failed = storage.raw_messages(true, None).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].raw_message.payload, vec![1, 2, 3]);
+        assert_eq!(storage.raw_messages(false, Some("Santa_Gracia")).unwrap().len(), 2);
+        assert_eq!(storage.raw_messages(false, Some("La_Campana")).unwrap().len(), 0);
+
+        let stored = &storage.raw_messages(false, None).unwrap()[0];
+        let (import_count, problems) = reprocess_raw_message(&mut storage, &station, stored).unwrap();
+        assert_eq!(import_count, ImportCount { inserted: 0, updated: 0, unchanged: 1 });
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn test_skipped_raw_messages() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[0..4] {
+            storage.apply_migration(migration).unwrap();
+        }
+
+        let imported = RawMessage::new("300025060007390_000001.sbd", Some("Santa_Gracia"), SIMPLE.to_vec(), None);
+        import_raw_to_db(&mut storage, &station("Santa_Gracia"), &imported, None).unwrap();
+
+        // The rows are kept when the station column becomes optional
+        migrate(&mut storage).unwrap();
+        assert_eq!(storage.raw_messages(false, Some("Santa_Gracia")).unwrap()[0].raw_message, imported);
+
+        // An imported payload stays as it is, a payload of an unknown station is archived without station
+        archive_skipped(&mut storage, &RawMessage::new("other.sbd", None, SIMPLE.to_vec(), None), "Unknown station").unwrap();
+        let unknown = RawMessage::new("300025060007390_000002.sbd", None, MULTIPLE.to_vec(), None);
+        archive_skipped(&mut storage, &unknown, "Unknown IMEI: 300025060007390").unwrap();
+        assert!(!storage.is_imported(&unknown.sha256).unwrap());
+
+        let skipped = storage.raw_messages(true, None).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].raw_message, unknown);
+
+        // Once the station is known, reprocess imports the records and sets the station
+        let (import_count, _) = reprocess_raw_message(&mut storage, &station("Santa_Gracia"), &skipped[0]).unwrap();
+        assert_eq!(import_count, ImportCount { inserted: 1, updated: 0, unchanged: 0 });
+        assert!(storage.is_imported(&unknown.sha256).unwrap());
+        assert_eq!(storage.raw_messages(false, Some("Santa_Gracia")).unwrap().len(), 2);
+    }
+}
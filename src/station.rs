@@ -0,0 +1,342 @@
+// External modules:
+use regex::Regex;
+use chrono::{NaiveDate};
+use toml;
+
+// System modules:
+use std::path::Path;
+use std::fs::File;
+use std::io::Read;
+use std::collections::HashSet;
+use std::sync::{OnceLock};
+
+// Internal modules:
+use error::{Result, ResultExt};
+use layout::{RecordLayout, Strictness, builtin_layouts};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Station {
+    pub imei: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: f64,
+    pub installation_date: Option<NaiveDate>,
+    pub layout: RecordLayout,
+}
+
+/// A station as given in the config file, the layout is referenced by name.
+#[derive(Debug, Deserialize)]
+struct StationConfig {
+    imei: String,
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    elevation: f64,
+    installation_date: Option<NaiveDate>,
+    layout: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SbdFileName {
+    pub imei: String,
+    pub momsn: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationFile {
+    station: Vec<StationConfig>,
+    #[serde(default)]
+    layout: Vec<RecordLayout>,
+}
+
+pub struct StationRegistry {
+    stations: Vec<Station>,
+    layouts: Vec<RecordLayout>,
+}
+
+impl StationRegistry {
+    /// layouts: all layouts that can be used, including the built in ones.
+    pub fn new(stations: Vec<Station>, layouts: Vec<RecordLayout>) -> Result<StationRegistry> {
+        let imei_re = Regex::new(r"^\d{15}$").unwrap();
+        let mut imeis = HashSet::new();
+        let mut names = HashSet::new();
+        let mut layout_names = HashSet::new();
+
+        for layout in &layouts {
+            layout.validate()?;
+
+            if !layout_names.insert(&layout.name) {
+                bail!("Duplicate layout name: '{}'", layout.name);
+            }
+        }
+
+        for station in &stations {
+            if !imei_re.is_match(&station.imei) {
+                bail!("Invalid IMEI for station '{}': '{}', expected 15 digits", station.name, station.imei);
+            }
+            if station.name.is_empty() {
+                bail!("Empty station name for IMEI: '{}'", station.imei);
+            }
+            if !imeis.insert(&station.imei) {
+                bail!("Duplicate IMEI in station registry: '{}'", station.imei);
+            }
+            if !names.insert(&station.name) {
+                bail!("Duplicate station name in station registry: '{}'", station.name);
+            }
+        }
+
+        Ok(StationRegistry {
+            stations,
+            layouts,
+        })
+    }
+
+    pub fn from_toml(content: &str) -> Result<StationRegistry> {
+        let station_file: StationFile = toml::from_str(content)?;
+
+        let mut layouts = builtin_layouts();
+        layouts.extend(station_file.layout);
+
+        let stations = station_file.station.into_iter().map(|config| {
+            let layout = match layouts.iter().find(|layout| layout.name == config.layout) {
+                Some(layout) => layout.clone(),
+                None => bail!("Unknown layout '{}' for station '{}'", config.layout, config.name)
+            };
+
+            Ok(Station {
+                imei: config.imei,
+                name: config.name,
+                latitude: config.latitude,
+                longitude: config.longitude,
+                elevation: config.elevation,
+                installation_date: config.installation_date,
+                layout,
+            })
+        }).collect::<Result<Vec<_>>>()?;
+
+        StationRegistry::new(stations, layouts)
+    }
+
+    pub fn load(file_name: &str) -> Result<StationRegistry> {
+        let mut content = String::new();
+        File::open(file_name)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .chain_err(|| format!("Could not read station registry: '{}'", file_name))?;
+
+        let registry = StationRegistry::from_toml(&content)
+            .chain_err(|| format!("Invalid station registry: '{}'", file_name))?;
+
+        info!("Station registry loaded from '{}', number of stations: {}", file_name, registry.stations.len());
+
+        Ok(registry)
+    }
+
+    pub fn find_by_imei(&self, imei: &str) -> Option<&Station> {
+        self.stations.iter().find(|station| station.imei == imei)
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&Station> {
+        self.stations.iter().find(|station| station.name == name)
+    }
+
+    /// Use the given strictness for all layouts, instead of the one from the config file.
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        for layout in &mut self.layouts {
+            layout.strictness = strictness;
+        }
+
+        for station in &mut self.stations {
+            station.layout.strictness = strictness;
+        }
+    }
+
+    /// Enable the recovery of corrupted records for all layouts.
+    pub fn set_recovery(&mut self) {
+        for layout in &mut self.layouts {
+            layout.recovery = true;
+        }
+
+        for station in &mut self.stations {
+            station.layout.recovery = true;
+        }
+    }
+
+    /// A built in layout or one from the config file.
+    pub fn layout(&self, name: &str) -> Result<&RecordLayout> {
+        match self.layouts.iter().find(|layout| layout.name == name) {
+            Some(layout) => Ok(layout),
+            None => bail!("Unknown record layout: '{}'", name)
+        }
+    }
+
+    /// Determine the station for the given SBD file.
+    /// The IMEI in the file name has priority, an explicit station name is only used to override
+    /// it when the file name does not contain a known IMEI. If both are given and they disagree an error is returned.
+    pub fn resolve(&self, file_name: &str, station_override: Option<&str>) -> Result<&Station> {
+        let detected = match parse_sbd_file_name(file_name) {
+            Some(sbd_file_name) => {
+                match self.find_by_imei(&sbd_file_name.imei) {
+                    Some(station) => {
+                        info!("Station '{}' detected from IMEI: {}, MOMSN: {}", station.name, sbd_file_name.imei, sbd_file_name.momsn);
+                        Some(station)
+                    },
+                    None => {
+                        warn!("Unknown IMEI in file name: '{}'", sbd_file_name.imei);
+                        None
+                    }
+                }
+            },
+            None => {
+                warn!("File name is not in the Iridium format <IMEI>_<MOMSN>.sbd: '{}'", file_name);
+                None
+            }
+        };
+
+        match (detected, station_override) {
+            (Some(station), Some(name)) => {
+                if station.name == name {
+                    Ok(station)
+                } else {
+                    bail!("Station given on command line: '{}' does not match station detected from IMEI {}: '{}'",
+                        name, station.imei, station.name)
+                }
+            },
+            (Some(station), None) => {
+                Ok(station)
+            },
+            (None, Some(name)) => {
+                match self.find_by_name(name) {
+                    Some(station) => Ok(station),
+                    None => bail!("Station '{}' is not in the station registry", name)
+                }
+            },
+            (None, None) => {
+                bail!("Could not determine station from file name: '{}', use --station to set it explicitly", file_name)
+            }
+        }
+    }
+}
+
+/// Extract IMEI and MOMSN from an Iridium file name: <IMEI>_<MOMSN>.sbd
+pub fn parse_sbd_file_name(file_name: &str) -> Option<SbdFileName> {
+    let base_name = Path::new(file_name).file_name()?.to_str()?;
+
+    static SBD_FILE_NAME_RE: OnceLock<Regex> = OnceLock::new();
+    let re = SBD_FILE_NAME_RE.get_or_init(|| Regex::new(r"^(\d{15})_(\d+)\.sbd$").unwrap());
+    let captures = re.captures(base_name)?;
+
+    Some(SbdFileName {
+        imei: captures[1].to_string(),
+        momsn: captures[2].parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use database::{Table};
+
+    use super::{StationRegistry, SbdFileName, parse_sbd_file_name};
+
+    const STATIONS: &str = r#"
+        [[station]]
+        imei = "300025060004660"
+        name = "La_Campana"
+        latitude = -32.9555
+        longitude = -71.0634
+        elevation = 730.0
+        installation_date = "2016-03-20"
+        layout = "simple_and_multiple"
+
+        [[station]]
+        imei = "300025060008580"
+        name = "Nahuelbuta"
+        latitude = -37.8077
+        longitude = -72.9514
+        elevation = 1200.0
+        layout = "multiple"
+
+        [[station]]
+        imei = "300025060001230"
+        name = "Fray_Jorge"
+        latitude = -30.6806
+        longitude = -71.6839
+        elevation = 600.0
+        layout = "cr1000x_hourly"
+
+        [[layout]]
+        name = "cr1000x_hourly"
+
+        [[layout.record]]
+        name = "hourly"
+        table = "multiple_data"
+        repeated = true
+        fields = [
+            { name = "air_temperature", type = "FP2", column = "air_temperature" },
+            { name = "wind_gust", type = "FP2", column = "wind_max" },
+        ]
+    "#;
+
+    #[test]
+    fn test_parse_sbd_file_name() {
+        assert_eq!(parse_sbd_file_name("/var/mail/sbd/300025060007390_002143.sbd"),
+            Some(SbdFileName {
+                imei: "300025060007390".to_string(),
+                momsn: 2143,
+            })
+        );
+        assert_eq!(parse_sbd_file_name("300025060007390.sbd"), None);
+        assert_eq!(parse_sbd_file_name("data.bin"), None);
+    }
+
+    #[test]
+    fn test_load_registry() {
+        let registry = StationRegistry::from_toml(STATIONS).unwrap();
+        let station = registry.find_by_name("Nahuelbuta").unwrap();
+
+        assert_eq!(station.imei, "300025060008580");
+        assert_eq!(station.layout.name, "multiple");
+        assert_eq!(station.installation_date, None);
+
+        let station = registry.find_by_name("Fray_Jorge").unwrap();
+        assert_eq!(station.layout.records[0].table, Table::MultipleData);
+        assert_eq!(station.layout.records[0].columns(), vec!["air_temperature", "wind_max"]);
+        assert_eq!(registry.layout("cr1000x_hourly").unwrap(), &station.layout);
+    }
+
+    #[test]
+    fn test_registry_rejects_unknown_layout() {
+        let unknown_layout = STATIONS.replace(r#"layout = "multiple""#, r#"layout = "cr300""#);
+        assert!(StationRegistry::from_toml(&unknown_layout).is_err());
+
+        let builtin_name = STATIONS.replace(r#"name = "cr1000x_hourly""#, r#"name = "simple""#);
+        assert!(StationRegistry::from_toml(&builtin_name).is_err());
+    }
+
+    #[test]
+    fn test_registry_rejects_duplicates() {
+        let duplicate_imei = STATIONS.replace("300025060008580", "300025060004660");
+        assert!(StationRegistry::from_toml(&duplicate_imei).is_err());
+
+        let duplicate_name = STATIONS.replace("Nahuelbuta", "La_Campana");
+        assert!(StationRegistry::from_toml(&duplicate_name).is_err());
+    }
+
+    #[test]
+    fn test_resolve_station() {
+        let registry = StationRegistry::from_toml(STATIONS).unwrap();
+
+        let station = registry.resolve("300025060004660_000012.sbd", None).unwrap();
+        assert_eq!(station.name, "La_Campana");
+
+        let station = registry.resolve("300025060004660_000012.sbd", Some("La_Campana")).unwrap();
+        assert_eq!(station.name, "La_Campana");
+
+        assert!(registry.resolve("300025060004660_000012.sbd", Some("Nahuelbuta")).is_err());
+        assert!(registry.resolve("data.bin", None).is_err());
+        assert!(registry.resolve("data.bin", Some("Santa_Gracia")).is_err());
+
+        let station = registry.resolve("data.bin", Some("Nahuelbuta")).unwrap();
+        assert_eq!(station.imei, "300025060008580");
+    }
+}
@@ -0,0 +1,34 @@
+//! Payloads and a station shared by the tests of several modules.
+
+use chrono::{NaiveDateTime};
+
+use layout::{builtin_layout};
+use station::{Station};
+
+/// One simple record (battery data) of 2016-09-19 00:00:00
+pub const SIMPLE: [u8; 14] = [0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 96, 0, 0, 0];
+/// One multiple record (full data) of 2016-09-19 00:00:00
+pub const MULTIPLE: [u8; 28] = [0, 141, 64, 50, 0, 0, 0, 0, 69, 222, 35, 229, 92, 249, 96, 77, 70, 100, 97, 103, 98, 238, 43, 190, 99, 232, 3, 194];
+
+/// The timestamp of SIMPLE and MULTIPLE
+pub fn date_time() -> NaiveDateTime {
+    NaiveDateTime::parse_from_str("2016-09-19 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+}
+
+/// When the test payloads were received, the session time of the Iridium e-mail
+pub fn received_at() -> NaiveDateTime {
+    NaiveDateTime::parse_from_str("2016-09-19 00:05:19", "%Y-%m-%d %H:%M:%S").unwrap()
+}
+
+/// The station of the test files (IMEI 300025060007390) with the layout simple_and_multiple.
+pub fn station(name: &str) -> Station {
+    Station {
+        imei: "300025060007390".to_string(),
+        name: name.to_string(),
+        latitude: -29.7574,
+        longitude: -71.1612,
+        elevation: 707.0,
+        installation_date: None,
+        layout: builtin_layout("simple_and_multiple").unwrap(),
+    }
+}
@@ -0,0 +1,198 @@
+// External modules:
+use inotify::{Inotify, WatchMask};
+
+// System modules:
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// Internal modules:
+use error::{Result, ResultExt};
+use database::{Storage};
+use migration::{check_schema_version};
+use batch::{FileResult, collect_files, sort_by_momsn, import_file};
+use station::{StationRegistry};
+
+/// Time to wait before connecting again after the database was not reachable.
+const RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// The directories used by the watch mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchDirs {
+    pub inbox: PathBuf,
+    pub processed: PathBuf,
+    pub failed: PathBuf,
+}
+
+fn file_name(file: &Path) -> String {
+    file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Move the file into the directory, the directory is created if needed.
+/// An existing file is never overwritten, e.g. by a message the gateway sent again:
+/// the name gets a number instead, 300025060007390_000001.1.sbd.
+fn move_file(file: &Path, directory: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(directory).chain_err(|| format!("Could not create directory: '{}'", directory.display()))?;
+
+    let name = file_name(file);
+    let (stem, extension) = match name.rfind('.') {
+        Some(index) => name.split_at(index),
+        None => (name.as_str(), ""),
+    };
+
+    for number in 0.. {
+        let target = if number == 0 {
+            directory.join(&name)
+        } else {
+            directory.join(format!("{}.{}{}", stem, number, extension))
+        };
+
+        // Unlike rename a hard link fails if the target exists
+        match fs::hard_link(file, &target) {
+            Ok(()) => {
+                fs::remove_file(file).chain_err(|| format!("Could not remove '{}' after moving it", file.display()))?;
+                return Ok(target)
+            },
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).chain_err(|| format!("Could not move '{}' to '{}'", file.display(), target.display())),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Move a file that could not be imported to the failed directory, the reason is written to <file>.error next to it.
+fn move_failed(file: &Path, dirs: &WatchDirs, reason: &str) -> Result<()> {
+    let target = move_file(file, &dirs.failed)?;
+    let error_file = dirs.failed.join(format!("{}.error", file_name(&target)));
+
+    File::create(&error_file)
+        .and_then(|mut file| writeln!(file, "{}", reason))
+        .chain_err(|| format!("Could not write error file: '{}'", error_file.display()))?;
+
+    error!("Moved file to '{}': {}", target.display(), reason);
+
+    Ok(())
+}
+
+/// Connect to the database, wait and try again until it is reachable.
+pub fn connect<F>(open_storage: &F) -> Box<dyn Storage> where F: Fn() -> Result<Box<dyn Storage>> {
+    loop {
+        match open_storage().and_then(|mut storage| check_schema_version(&mut *storage).map(|_| storage)) {
+            Ok(storage) => return storage,
+            Err(e) => {
+                error!("Could not connect to database, trying again in {} seconds: {}", RECONNECT_DELAY.as_secs(), e);
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+/// Import all .sbd files that are in the inbox, in MOMSN order.
+/// Files are only moved out of the inbox after they are imported or known to be broken,
+/// if the database connection is lost the file stays in the inbox and is imported after reconnecting.
+/// A file that is still in the inbox after it was imported, e.g. because the importer was stopped
+/// before it was moved, is recognized by the SHA-256 of its payload and only moved.
+fn process_inbox<F>(storage: &mut Option<Box<dyn Storage>>, open_storage: &F, station_registry: &StationRegistry,
+        dirs: &WatchDirs) -> Result<()> where F: Fn() -> Result<Box<dyn Storage>> {
+    let mut files = collect_files(&[&dirs.inbox.to_string_lossy()])?;
+    sort_by_momsn(&mut files);
+
+    let mut index = 0;
+
+    while index < files.len() {
+        let file = &files[index];
+        let db_storage = storage.get_or_insert_with(|| connect(open_storage));
+
+        match import_file(&mut **db_storage, station_registry, file, None, false) {
+            FileResult::Imported(import_count, problems) => {
+                info!("Imported file: '{}', inserted: {}, updated: {}, unchanged: {}",
+                    file.display(), import_count.inserted, import_count.updated, import_count.unchanged);
+                for problem in problems {
+                    warn!("Parse problem in file: '{}': {}", file.display(), problem);
+                }
+            },
+            FileResult::Duplicate(sha256) => info!("File already imported: '{}', sha256: {}", file.display(), sha256),
+            FileResult::Skipped(reason) | FileResult::Failed(reason) => {
+                // Distinguish a broken file from a lost connection
+                if let Err(e) = db_storage.schema_version() {
+                    error!("Database connection lost, file stays in inbox: '{}': {}", file.display(), e);
+                    *storage = None;
+                    continue;
+                }
+
+                move_failed(file, dirs, &reason)?;
+                index += 1;
+                continue;
+            }
+        }
+
+        let station_dir = match station_registry.resolve(&file.to_string_lossy(), None) {
+            Ok(station) => dirs.processed.join(&station.name),
+            Err(_) => dirs.processed.clone(),
+        };
+
+        move_file(file, &station_dir)?;
+        index += 1;
+    }
+
+    Ok(())
+}
+
+/// Watch the inbox with inotify and import every .sbd file written or moved into it.
+/// Files already in the inbox when the watch starts are imported first.
+pub fn watch<F>(dirs: &WatchDirs, station_registry: &StationRegistry, open_storage: F) -> Result<()> where F: Fn() -> Result<Box<dyn Storage>> {
+    fs::create_dir_all(&dirs.processed).chain_err(|| format!("Could not create directory: '{}'", dirs.processed.display()))?;
+    fs::create_dir_all(&dirs.failed).chain_err(|| format!("Could not create directory: '{}'", dirs.failed.display()))?;
+
+    // Add the watch before the first scan, so no file written in between is missed
+    let mut inotify = Inotify::init().chain_err(|| "Could not initialize inotify")?;
+    inotify.watches().add(&dirs.inbox, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        .chain_err(|| format!("Could not watch directory: '{}'", dirs.inbox.display()))?;
+
+    info!("Watching directory: '{}'", dirs.inbox.display());
+
+    let mut storage = None;
+    let mut buffer = [0; 4096];
+
+    loop {
+        process_inbox(&mut storage, &open_storage, station_registry, dirs)?;
+
+        let events = inotify.read_events_blocking(&mut buffer)?;
+        let new_files = events.filter(|event| event.name.is_some_and(|name| name.to_string_lossy().ends_with(".sbd"))).count();
+
+        info!("New files in inbox: {}", new_files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{move_file};
+
+    #[test]
+    fn test_move_file_keeps_existing() {
+        let dir = env::temp_dir().join(format!("sbd_db_import_watch_{}", ::std::process::id()));
+        let inbox = dir.join("inbox");
+        let processed = dir.join("processed");
+        fs::create_dir_all(&inbox).unwrap();
+
+        let file = inbox.join("300025060007390_000001.sbd");
+
+        fs::write(&file, [1]).unwrap();
+        assert_eq!(move_file(&file, &processed).unwrap(), processed.join("300025060007390_000001.sbd"));
+
+        fs::write(&file, [2]).unwrap();
+        assert_eq!(move_file(&file, &processed).unwrap(), processed.join("300025060007390_000001.1.sbd"));
+        assert!(!file.exists());
+
+        assert_eq!(fs::read(processed.join("300025060007390_000001.sbd")).unwrap(), vec![1]);
+        assert_eq!(fs::read(processed.join("300025060007390_000001.1.sbd")).unwrap(), vec![2]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}